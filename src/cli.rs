@@ -0,0 +1,236 @@
+//! Command line entry point, shared by the `imspect` binary and the Python console script.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use eframe::egui::Vec2;
+
+use crate::imspect_app::run::{imspect_kornia_images, PanelLayout, ViewerOptions};
+use crate::input::{bgr_to_rgb, expand_archives, load_images, LoadError, STDIN};
+use crate::ipc::receive_images;
+use crate::paths::Expansion;
+
+/// Some files couldn't be loaded or the viewer failed.
+const EXIT_FAILURE: u8 = 1;
+/// The arguments don't make sense together.
+const EXIT_USAGE: u8 = 2;
+/// More files than this are browsed in the gallery instead of being loaded at start.
+const GALLERY_MIN: usize = 64;
+
+/// Explore features of images during debug session
+#[derive(Parser)]
+#[command(name = "imspect", version)]
+struct Cli {
+    /// Images to open, `.npy` arrays or any common image format.
+    /// `.npz` archives open every array they hold, `bundle.npz/KEY` a single one.
+    /// Directories and quoted glob patterns open every image they contain, `-` reads stdin.
+    /// Headerless files take their layout after the path, e.g. `frame.raw:640x480:u16le`
+    /// or `frame.yuv:1920x1080:nv12`
+    #[arg(required_unless_present = "ipc")]
+    paths: Vec<PathBuf>,
+
+    /// Also open images in subdirectories of the given directories
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Extensions picked from directories and patterns, e.g. `png,npy`
+    #[arg(long = "ext", value_name = "EXT", value_delimiter = ',')]
+    extensions: Vec<String>,
+
+    /// Require the image piped to stdin to be a `.npy` array
+    #[arg(long)]
+    npy: bool,
+
+    /// Title of the window
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Interpret 3- and 4-channel arrays and raw buffers as BGR(A), the order OpenCV
+    /// keeps in memory. Image files are decoded in RGB order and left as they are
+    #[arg(long)]
+    bgr: bool,
+
+    /// How the image panels are arranged
+    #[arg(long, value_enum, default_value_t)]
+    layout: PanelLayout,
+
+    /// Initial size of the window, e.g. 1280x720
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_window_size)]
+    window_size: Option<Vec2>,
+
+    /// UI scale, the scale of the display by default
+    #[arg(long, value_name = "SCALE")]
+    pixels_per_point: Option<f32>,
+
+    /// Open the images that loaded even if some files failed
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Reload images when their files change, recomputing the panels made from them
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Browse the files as thumbnails, decoding them on demand.
+    /// Used anyway for more than 64 files
+    #[arg(long)]
+    gallery: bool,
+
+    /// Read the images sent by `imspect()` from stdin
+    #[arg(long, hide = true, conflicts_with = "paths")]
+    ipc: bool,
+}
+
+fn parse_window_size(s: &str) -> Result<Vec2, String> {
+    let parsed = s
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<f32>().ok()?, h.parse::<f32>().ok()?)));
+    match parsed {
+        Some((w, h)) if w > 0. && h > 0. => Ok(Vec2::new(w, h)),
+        _ => Err(format!("expected WIDTHxHEIGHT, e.g. 1280x720, got '{}'", s)),
+    }
+}
+
+impl Cli {
+    fn viewer_options(&self) -> ViewerOptions {
+        ViewerOptions {
+            title: self.title.clone(),
+            panel_layout: self.layout,
+            window_size: self.window_size,
+            pixels_per_point: self.pixels_per_point,
+            watch: self.watch,
+            bgr: self.bgr,
+            ..Default::default()
+        }
+    }
+}
+
+/// Runs the viewer for the command line `args` and returns the process exit code.
+pub fn run(args: Vec<OsString>) -> u8 {
+    let cli = match Cli::try_parse_from(std::iter::once(OsString::from("imspect")).chain(args)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            // prints the usage error, or the help and version on stdout
+            let _ = err.print();
+            return err.exit_code() as u8;
+        }
+    };
+    if cli.ipc {
+        return run_from_python(cli.viewer_options());
+    };
+    let stdin_count = cli
+        .paths
+        .iter()
+        .filter(|path| *path == Path::new(STDIN))
+        .count();
+    if stdin_count > 1 {
+        eprintln!(
+            "imspect: stdin can only be read once, pass `{}` a single time",
+            STDIN
+        );
+        return EXIT_USAGE;
+    };
+    if cli.npy && stdin_count == 0 {
+        eprintln!(
+            "imspect: --npy applies to stdin, pass `{}` as a path",
+            STDIN
+        );
+        return EXIT_USAGE;
+    };
+
+    let expansion = Expansion {
+        recursive: cli.recursive,
+        extensions: cli
+            .extensions
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+            .collect(),
+    };
+    let (paths, mut errors) = expansion.expand(cli.paths.clone());
+    if cli.gallery || paths.len() > GALLERY_MIN {
+        // the gallery decodes its files again after evicting them
+        if stdin_count > 0 {
+            eprintln!("imspect: stdin can't be browsed in the gallery, save it to a file first");
+            return EXIT_USAGE;
+        };
+        let (paths, archive_errors) = expand_archives(paths);
+        errors.extend(archive_errors);
+        return run_gallery(&cli, paths, errors);
+    };
+    let (imgs, load_errors) = load_images(paths, cli.npy);
+    errors.extend(load_errors);
+    for error in &errors {
+        eprintln!("imspect: {}", error);
+    }
+    if !errors.is_empty() && !cli.keep_going {
+        eprintln!("imspect: pass --keep-going to open the images that did load");
+        return EXIT_FAILURE;
+    };
+    if imgs.is_empty() {
+        return EXIT_FAILURE;
+    };
+    let imgs = if cli.bgr {
+        imgs.into_iter().map(bgr_to_rgb).collect()
+    } else {
+        imgs
+    };
+
+    let options = ViewerOptions {
+        load_errors: errors.iter().map(ToString::to_string).collect(),
+        ..cli.viewer_options()
+    };
+    if let Err(err) = imspect_kornia_images(imgs, options) {
+        eprintln!("imspect: {}", err);
+        return EXIT_FAILURE;
+    };
+    if errors.is_empty() {
+        0
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+/// Opens the files in the gallery, decoding errors are shown in the window.
+fn run_gallery(cli: &Cli, paths: Vec<PathBuf>, errors: Vec<LoadError>) -> u8 {
+    for error in &errors {
+        eprintln!("imspect: {}", error);
+    }
+    if paths.is_empty() || (!errors.is_empty() && !cli.keep_going) {
+        return EXIT_FAILURE;
+    };
+    let options = ViewerOptions {
+        load_errors: errors.iter().map(ToString::to_string).collect(),
+        gallery: Some(paths),
+        ..cli.viewer_options()
+    };
+    if let Err(err) = imspect_kornia_images(Vec::new(), options) {
+        eprintln!("imspect: {}", err);
+        return EXIT_FAILURE;
+    };
+    if errors.is_empty() {
+        0
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+/// Viewer started by `imspect()` in Python, its images come over stdin.
+fn run_from_python(options: ViewerOptions) -> u8 {
+    let message = match receive_images() {
+        Ok(message) => message,
+        Err(err) => {
+            eprintln!("imspect: {:#}", err);
+            return EXIT_FAILURE;
+        }
+    };
+    let options = ViewerOptions {
+        listen: !message.block,
+        report_on_exit: message.block,
+        ..options
+    };
+    if let Err(err) = imspect_kornia_images(message.images, options) {
+        eprintln!("imspect: {}", err);
+        return EXIT_FAILURE;
+    };
+    0
+}
@@ -0,0 +1,123 @@
+//! Detection of the file format from the first bytes, so misnamed files still open.
+
+use std::fmt;
+
+use image::ImageFormat;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Npy,
+    Png,
+    Jpeg,
+    Tiff,
+    Pnm,
+    Bmp,
+    // recognized only to name them in errors
+    Zip,
+    Gzip,
+    Pdf,
+    Hdf5,
+}
+
+impl Format {
+    /// Guesses the format from the magic bytes at the start of the file.
+    pub fn sniff(bytes: &[u8]) -> Option<Format> {
+        let format = match bytes {
+            [0x93, b'N', b'U', b'M', b'P', b'Y', ..] => Format::Npy,
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Format::Png,
+            [0xff, 0xd8, 0xff, ..] => Format::Jpeg,
+            [b'I', b'I', b'*', 0, ..] | [b'M', b'M', 0, b'*', ..] => Format::Tiff,
+            // BigTIFF
+            [b'I', b'I', b'+', 0, ..] | [b'M', b'M', 0, b'+', ..] => Format::Tiff,
+            [b'P', b'1'..=b'7', sep, ..] if sep.is_ascii_whitespace() => Format::Pnm,
+            [b'B', b'M', ..] => Format::Bmp,
+            [b'P', b'K', 3, 4, ..] => Format::Zip,
+            [0x1f, 0x8b, ..] => Format::Gzip,
+            [b'%', b'P', b'D', b'F', ..] => Format::Pdf,
+            [0x89, b'H', b'D', b'F', b'\r', b'\n', 0x1a, b'\n', ..] => Format::Hdf5,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    /// Decoder for the image formats, `None` for arrays and unsupported formats.
+    pub fn image_format(&self) -> Option<ImageFormat> {
+        match self {
+            Format::Png => Some(ImageFormat::Png),
+            Format::Jpeg => Some(ImageFormat::Jpeg),
+            Format::Tiff => Some(ImageFormat::Tiff),
+            Format::Pnm => Some(ImageFormat::Pnm),
+            Format::Bmp => Some(ImageFormat::Bmp),
+            Format::Npy | Format::Zip | Format::Gzip | Format::Pdf | Format::Hdf5 => None,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Npy => "NPY",
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+            Format::Tiff => "TIFF",
+            Format::Pnm => "PNM",
+            Format::Bmp => "BMP",
+            Format::Zip => "ZIP",
+            Format::Gzip => "gzip",
+            Format::Pdf => "PDF",
+            Format::Hdf5 => "HDF5",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_magic_bytes() {
+        let cases: &[(&[u8], Format)] = &[
+            (b"\x93NUMPY\x01\x00", Format::Npy),
+            (b"\x89PNG\r\n\x1a\n", Format::Png),
+            (b"\xff\xd8\xff\xe0", Format::Jpeg),
+            (b"II*\x00", Format::Tiff),
+            (b"MM\x00*", Format::Tiff),
+            (b"II+\x00", Format::Tiff),
+            (b"P5\n640 480\n255\n", Format::Pnm),
+            (b"P6 2 2 255 ", Format::Pnm),
+            (b"BM\x00\x00", Format::Bmp),
+            (b"PK\x03\x04", Format::Zip),
+            (b"\x1f\x8b\x08", Format::Gzip),
+            (b"%PDF-1.7", Format::Pdf),
+            (b"\x89HDF\r\n\x1a\n", Format::Hdf5),
+        ];
+        for (bytes, format) in cases {
+            assert_eq!(Format::sniff(bytes), Some(*format), "{}", format);
+        }
+    }
+
+    #[test]
+    fn leaves_unknown_and_truncated_headers() {
+        for bytes in [
+            &b""[..],
+            b"\x93NUMP",
+            b"\x89PNG",
+            b"\xff\xd8",
+            b"P8\n",
+            b"P5",
+            b"PK\x05\x06",
+            b"hello",
+        ] {
+            assert_eq!(Format::sniff(bytes), None, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn only_image_formats_have_decoders() {
+        assert_eq!(Format::Png.image_format(), Some(ImageFormat::Png));
+        assert_eq!(Format::Tiff.image_format(), Some(ImageFormat::Tiff));
+        assert_eq!(Format::Npy.image_format(), None);
+        assert_eq!(Format::Zip.image_format(), None);
+    }
+}
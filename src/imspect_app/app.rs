@@ -1,18 +1,86 @@
+use std::collections::HashMap;
 use std::default::Default;
+use std::io;
 use std::ops::Neg;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use eframe::egui;
 use eframe::emath::Vec2b;
 use egui::style::ScrollStyle;
-use egui::{Align, ComboBox, Layout, Sides, Slider, Ui, Vec2};
+use egui::{
+    Align, Align2, Color32, ComboBox, FontId, Label, Layout, Pos2, Rect, Sense, Sides, Slider,
+    TextureHandle, Ui, Vec2, ViewportCommand,
+};
 use egui_plot::{Plot, PlotImage, PlotPoint};
 
-use crate::imspect_app::imspection::{ColorSpaceChange, ImageKind, SingleImspection, Threshold};
-use crate::imspect_app::textures::prepare_texture;
+use crate::imspect_app::bayer::{Demosaic, CFA_PATTERNS};
+use crate::imspect_app::gallery::Gallery;
+use crate::imspect_app::image_kind::{ColorSpaceChange, DType, NamedImage};
+use crate::imspect_app::imspection::{AlphaView, DisplayRange, SingleImspection, Threshold};
+use crate::imspect_app::paged_view::PagedView;
+use crate::imspect_app::raw_dialog::RawDialog;
+use crate::imspect_app::run::{PanelLayout, ViewerOptions};
+use crate::imspect_app::textures::{
+    prepare_detail, prepare_texture, prepare_tiles, Background, THUMBNAIL_SIZE,
+};
+use crate::imspect_app::watch::{watch, Watcher};
+use crate::input::file_title;
+use crate::ipc::{self, Incoming, Message};
+use crate::npz;
+use crate::yuv::YuvPlane;
+
+/// With more images than this, a thumbnail strip picks the ones shown as panels.
+const THUMBNAIL_STRIP_MIN: usize = 8;
+/// Panels shown at start when the thumbnail strip is used,
+/// and arrays of each `.npz` archive, the others are picked in the Arrays menu.
+const INITIALLY_SHOWN: usize = 4;
+
+/// Draws the texture scaled to fit `rect`, framed if `selected`.
+fn paint_thumbnail(ui: &Ui, rect: Rect, texture: Option<&TextureHandle>, selected: bool) {
+    if let Some(texture) = texture {
+        let size = texture.size_vec2();
+        let scale = rect.width().min(rect.height()) / size.max_elem();
+        ui.painter().image(
+            texture.id(),
+            Rect::from_center_size(rect.center(), size * scale),
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.)),
+            Color32::WHITE,
+        );
+    };
+    if selected {
+        ui.painter()
+            .rect_stroke(rect, 2., ui.visuals().selection.stroke);
+    };
+}
 
 #[derive(Default)]
 pub struct ImspectApp {
     imspections: Vec<SingleImspection>,
+    /// Images sent by later `imspect` calls, see [`crate::ipc::listen`].
+    incoming: Option<Incoming>,
+    /// Write the images to stdout when the window is closed, see [`ipc::write_results`].
+    report_on_exit: bool,
+    load_errors: Vec<String>,
+    panel_layout: PanelLayout,
+    gallery: Option<Gallery>,
+    /// New versions of files changed on disk, see [`watch`].
+    watcher: Option<Watcher>,
+    raw_dialog: RawDialog,
+    raw_dialog_open: bool,
+    /// Shown through transparent regions of gray+alpha and RGBA images.
+    background: Background,
+    /// Narrows the arrays listed in the Arrays menu.
+    array_filter: String,
+}
+
+/// The `.npz` archive a loaded image is an array of.
+fn archive_of(imspection: &SingleImspection) -> Option<&Path> {
+    if imspection.derived.is_some() {
+        return None;
+    };
+    let (archive, _) = npz::split_member(imspection.path.as_deref()?)?;
+    Some(archive)
 }
 
 impl ImspectApp {
@@ -38,26 +106,219 @@ impl ImspectApp {
     }
 
     /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>, imgs: Vec<ImageKind>) -> Self {
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        imgs: Vec<NamedImage>,
+        incoming: Option<Incoming>,
+        options: ViewerOptions,
+    ) -> Self {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
-        cc.egui_ctx.set_pixels_per_point(1.0);
+        if let Some(pixels_per_point) = options.pixels_per_point {
+            cc.egui_ctx.set_pixels_per_point(pixels_per_point);
+        };
 
+        let with_strip = imgs.len() > THUMBNAIL_STRIP_MIN;
+        let mut archive_counts: HashMap<PathBuf, usize> = HashMap::new();
         let imspections_vec: Vec<SingleImspection> = imgs
             .into_iter()
             .enumerate()
-            .map(|(i, img)| SingleImspection {
-                image: img,
-                texture: None,
-                id: i,
-                need_rerender: true,
-                remove_flag: false,
-                thr: Default::default(),
+            .map(|(i, img)| {
+                let imspection = SingleImspection::from_named(img, i);
+                let in_archive = archive_of(&imspection).map(|archive| {
+                    let count = archive_counts.entry(archive.to_owned()).or_default();
+                    *count += 1;
+                    *count
+                });
+                SingleImspection {
+                    shown: (!with_strip || i < INITIALLY_SHOWN)
+                        && in_archive.map_or(true, |n| n <= INITIALLY_SHOWN),
+                    ..imspection
+                }
             })
             .collect();
 
+        // panels opened later from the gallery or the raw dialog are added as they open
+        let watcher = options
+            .watch
+            .then(|| watch(cc.egui_ctx.clone(), options.bgr));
+        if let Some(watcher) = &watcher {
+            for path in imspections_vec.iter().filter_map(|imsp| imsp.path.as_ref()) {
+                watcher.add(path);
+            }
+        };
+
         Self {
             imspections: imspections_vec,
+            incoming,
+            report_on_exit: options.report_on_exit,
+            load_errors: options.load_errors,
+            panel_layout: options.panel_layout,
+            gallery: options
+                .gallery
+                .map(|paths| Gallery::new(cc.egui_ctx.clone(), paths, options.bgr)),
+            watcher,
+            ..Default::default()
+        }
+    }
+
+    fn receive_incoming(&mut self, ctx: &egui::Context) {
+        let Some(incoming) = &self.incoming else {
+            return;
+        };
+        let messages: Vec<Message> = incoming.messages.try_iter().collect();
+        if messages.is_empty() {
+            return;
+        };
+
+        for message in messages {
+            if message.replace {
+                self.imspections.clear();
+            };
+            for img in message.images {
+                self.add_image(img);
+            }
+        }
+        ctx.send_viewport_cmd(ViewportCommand::Focus);
+    }
+
+    fn add_image(&mut self, img: NamedImage) {
+        if let (Some(watcher), Some(path)) = (&self.watcher, &img.path) {
+            watcher.add(path);
+        };
+        let id = self.next_available_id();
+        self.imspections.push(SingleImspection::from_named(img, id));
+    }
+
+    /// Replaces the images of changed files and redoes the panels derived from them.
+    fn receive_reloads(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        let mut changed_ids = Vec::new();
+        for reloaded in watcher.reloads.try_iter().collect::<Vec<_>>() {
+            for imspection in self.imspections.iter_mut() {
+                if imspection.path.as_ref() == Some(&reloaded.path) {
+                    imspection.yuv = reloaded.yuv.clone();
+                    imspection.paged = reloaded.paged.clone().map(PagedView::new);
+                    imspection.replace_image(reloaded.image.clone(), reloaded.stack.clone());
+                    changed_ids.push(imspection.id);
+                };
+            }
+        }
+        self.rederive_children(changed_ids);
+    }
+
+    /// Samples the overviews of paged arrays in the background and shows them once done.
+    fn receive_overviews(&mut self, ctx: &egui::Context) {
+        let mut changed_ids = Vec::new();
+        for imspection in self.imspections.iter_mut() {
+            let Some(view) = &mut imspection.paged else {
+                continue;
+            };
+            match view.poll_overview(ctx) {
+                Some(Ok(overview)) => {
+                    imspection.replace_image(overview, None);
+                    changed_ids.push(imspection.id);
+                }
+                Some(Err(error)) => self
+                    .load_errors
+                    .push(format!("{}: {}", imspection.title, error)),
+                None => {}
+            };
+        }
+        self.rederive_children(changed_ids);
+    }
+
+    /// Redoes the panels derived from the changed ones.
+    fn rederive_children(&mut self, mut changed_ids: Vec<usize>) {
+        if changed_ids.is_empty() {
+            return;
+        };
+
+        // derived panels are added after their parents, so one pass redoes whole chains
+        for idx in 0..self.imspections.len() {
+            let Some(derivation) = self.imspections[idx].derived else {
+                continue;
+            };
+            if !changed_ids.contains(&derivation.parent_id) {
+                continue;
+            };
+            let (parents, rest) = self.imspections.split_at_mut(idx);
+            let Some(parent) = parents
+                .iter()
+                .find(|imspection| imspection.id == derivation.parent_id)
+            else {
+                continue;
+            };
+            if rest[0].rederive(parent).is_ok() {
+                changed_ids.push(rest[0].id);
+            };
+        }
+    }
+
+    fn receive_gallery(&mut self, ctx: &egui::Context) {
+        let Some(gallery) = &mut self.gallery else {
+            return;
+        };
+        let update = gallery.receive(ctx);
+        self.load_errors.extend(update.errors);
+        for img in update.open {
+            self.add_image(img);
+        }
+    }
+
+    /// Thumbnails of the gallery files, only the visible rows are laid out and decoded.
+    fn render_gallery(&mut self, ctx: &egui::Context) {
+        let Some(gallery) = &mut self.gallery else {
+            return;
+        };
+        let mut opened = Vec::new();
+        egui::SidePanel::left("Gallery")
+            .default_width(3. * (THUMBNAIL_SIZE as f32 + 8.))
+            .show(ctx, |ui| {
+                ui.strong(format!("{} files", gallery.entries.len()));
+                let cell = THUMBNAIL_SIZE as f32 + ui.spacing().item_spacing.x;
+                let per_row = ((ui.available_width() / cell).floor() as usize).max(1);
+                let rows = gallery.entries.len().div_ceil(per_row);
+                egui::ScrollArea::vertical()
+                    .id_salt("Gallery scroll area")
+                    .show_rows(ui, THUMBNAIL_SIZE as f32, rows, |ui, visible_rows| {
+                        for row in visible_rows {
+                            ui.horizontal(|ui| {
+                                let end = ((row + 1) * per_row).min(gallery.entries.len());
+                                for idx in row * per_row..end {
+                                    let (rect, response) = ui.allocate_exact_size(
+                                        Vec2::splat(THUMBNAIL_SIZE as f32),
+                                        Sense::click(),
+                                    );
+                                    let hover = match gallery.error(idx) {
+                                        Some(error) => {
+                                            ui.painter().text(
+                                                rect.center(),
+                                                Align2::CENTER_CENTER,
+                                                "failed",
+                                                FontId::default(),
+                                                ui.visuals().error_fg_color,
+                                            );
+                                            format!("{}\n{}", gallery.entries[idx].title, error)
+                                        }
+                                        None => {
+                                            let texture = gallery.thumbnail(idx);
+                                            paint_thumbnail(ui, rect, texture, false);
+                                            gallery.entries[idx].title.clone()
+                                        }
+                                    };
+                                    if response.on_hover_text(hover).clicked() {
+                                        opened.extend(gallery.open(idx));
+                                    };
+                                }
+                            });
+                        }
+                    });
+            });
+        for img in opened {
+            self.add_image(img);
         }
     }
 
@@ -67,7 +328,8 @@ impl ImspectApp {
             .get_mut(idx)
             .expect("Imspectction by index exists");
 
-        if let ImageKind::OneChannel(_) = &imspection.image {
+        if imspection.image.num_channels() == 1 {
+            let previous_kind = imspection.thr.kind;
             ComboBox::from_id_salt(imspection.id)
                 .selected_text(format!("Thresholding: {}", imspection.thr.kind))
                 .show_ui(ui, |ui| {
@@ -102,67 +364,152 @@ impl ImspectApp {
                         Threshold::Truncate.to_string(),
                     );
                 });
+            if imspection.thr.kind != previous_kind {
+                imspection.need_rerender = true;
+            };
             if !matches!(imspection.thr.kind, Threshold::None) {
                 ui.ctx().style_mut(|style| {
                     style.spacing.slider_width = ui.available_width() - 50.;
                 });
-                if ui
-                    .add(Slider::new(&mut imspection.thr.value, 0..=255))
-                    .changed()
-                {
+                let (lo, hi) = imspection.value_range;
+                let mut slider = Slider::new(&mut imspection.thr.value, lo..=hi);
+                if imspection.image.dtype().is_integer() {
+                    slider = slider.step_by(1.);
+                };
+                if ui.add(slider).changed() {
                     imspection.need_rerender = true;
                 }
             };
         };
     }
 
+    fn render_display_range(&mut self, ui: &mut Ui, idx: usize) {
+        let imspection = self
+            .imspections
+            .get_mut(idx)
+            .expect("Imspectction by index exists");
+
+        if matches!(imspection.image.dtype(), DType::U8) {
+            return;
+        };
+        let previous = imspection.display;
+        ComboBox::from_id_salt(format!("display_{}", imspection.id))
+            .selected_text(format!(
+                "{} display: {}",
+                imspection.image.dtype(),
+                imspection.display
+            ))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(
+                    &mut imspection.display,
+                    DisplayRange::Auto,
+                    DisplayRange::Auto.to_string(),
+                );
+                ui.selectable_value(
+                    &mut imspection.display,
+                    DisplayRange::Dtype,
+                    DisplayRange::Dtype.to_string(),
+                );
+                let is_window = matches!(imspection.display, DisplayRange::Window { .. });
+                if ui.selectable_label(is_window, "Window").clicked() && !is_window {
+                    // start from the range currently shown
+                    let (min, max) = imspection.display_range(&imspection.image);
+                    imspection.display = DisplayRange::Window { min, max };
+                };
+            });
+        if let DisplayRange::Window { min, max } = &mut imspection.display {
+            let (lo, hi) = imspection.value_range;
+            let integer = imspection.image.dtype().is_integer();
+            for (value, label) in [(min, "min"), (max, "max")] {
+                let mut slider = Slider::new(value, lo..=hi).text(label);
+                if integer {
+                    slider = slider.step_by(1.);
+                };
+                ui.add(slider);
+            }
+        };
+        if imspection.display != previous {
+            imspection.need_rerender = true;
+        };
+    }
+
+    /// Slice slider and playback of stacked images.
+    fn render_stack_controls(&mut self, ui: &mut Ui, idx: usize) {
+        let imspection = self
+            .imspections
+            .get_mut(idx)
+            .expect("Imspectction by index exists");
+        let Some(stack) = &mut imspection.stack else {
+            return;
+        };
+
+        let mut moved = false;
+        ui.horizontal(|ui| {
+            let play = if stack.playing { "⏸" } else { "▶" };
+            if ui.button(play).clicked() {
+                stack.toggle_playing();
+                moved = true;
+            };
+            let last = stack.slice_count() - 1;
+            let slider = Slider::new(&mut stack.current, 0..=last).text(format!("/ {}", last));
+            moved |= ui.add(slider).changed();
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut stack.looping, "Loop");
+            ui.add(
+                egui::DragValue::new(&mut stack.fps)
+                    .range(1.0..=60.0)
+                    .suffix(" fps"),
+            );
+            ui.checkbox(&mut stack.whole_stack, "All slices")
+                .on_hover_text("Clone, channel and color operations apply to every slice");
+        });
+        if moved {
+            imspection.show_current_slice();
+        };
+    }
+
+    /// Moves playing stacks forward and schedules the next frame for them.
+    fn advance_stacks(&mut self, ctx: &egui::Context) {
+        let now = ctx.input(|input| input.time);
+        let mut next_step: Option<f32> = None;
+        for imspection in self.imspections.iter_mut() {
+            let Some(stack) = &mut imspection.stack else {
+                continue;
+            };
+            if stack.advance(now) {
+                imspection.show_current_slice();
+            };
+            if let Some(stack) = &imspection.stack {
+                if stack.playing {
+                    let interval = 1. / stack.fps;
+                    next_step = Some(next_step.map_or(interval, |step| step.min(interval)));
+                };
+            };
+        }
+        if let Some(step) = next_step {
+            ctx.request_repaint_after(Duration::from_secs_f32(step));
+        };
+    }
+
     fn render_color_conversions(&mut self, ui: &mut Ui, idx: usize) {
         let mut new_imspection_to_add: Option<SingleImspection> = None;
 
+        let imspection = &self.imspections[idx];
+        let conversions = ColorSpaceChange::available_for(imspection.image.num_channels());
+        if conversions.is_empty() {
+            return;
+        };
+
         ui.menu_button("Change color space", |ui| {
-            let image = &self.imspections.get(idx).unwrap().image;
-            match &image {
-                ImageKind::OneChannel(_) => {
-                    if ui.button("GRAY => RGB").clicked() {
-                        if let Ok(new_imspection) = SingleImspection::new_with_changed_color(
-                            image,
-                            ColorSpaceChange::GRAY2RGB,
-                            self.next_available_id(),
-                        ) {
-                            new_imspection_to_add = Some(new_imspection)
-                        };
-                        ui.close_menu();
-                    }
-                }
-                ImageKind::ThreeChannel(_) => {
-                    if ui.button("BGR => RGB").clicked() {
-                        if let Ok(new_imspection) = SingleImspection::new_with_changed_color(
-                            image,
-                            ColorSpaceChange::BGR2RGB,
-                            self.next_available_id(),
-                        ) {
-                            new_imspection_to_add = Some(new_imspection)
-                        };
-                        ui.close_menu();
-                    } else if ui.button("RGB => GRAY").clicked() {
-                        if let Ok(new_imspection) = SingleImspection::new_with_changed_color(
-                            image,
-                            ColorSpaceChange::RGB2GRAY,
-                            self.next_available_id(),
-                        ) {
-                            new_imspection_to_add = Some(new_imspection)
-                        };
-                        ui.close_menu();
-                    } else if ui.button("RGB => HSV").clicked() {
-                        if let Ok(new_imspection) = SingleImspection::new_with_changed_color(
-                            image,
-                            ColorSpaceChange::RGB2HSV,
-                            self.next_available_id(),
-                        ) {
-                            new_imspection_to_add = Some(new_imspection)
-                        };
-                        ui.close_menu();
-                    }
+            for color in conversions {
+                if ui.button(color.to_string()).clicked() {
+                    if let Ok(new_imspection) =
+                        imspection.new_with_changed_color(*color, self.next_available_id())
+                    {
+                        new_imspection_to_add = Some(new_imspection)
+                    };
+                    ui.close_menu();
                 }
             }
         });
@@ -175,17 +522,14 @@ impl ImspectApp {
         let imspection = &self.imspections[idx];
         let mut new_imspection: Option<SingleImspection> = None;
 
-        if let ImageKind::ThreeChannel(img) = &imspection.image {
+        if imspection.image.num_channels() > 1 {
             ui.menu_button("Extract channel", |ui| {
                 ui.horizontal_top(|ui| {
-                    for i in 0..(img.num_channels()) {
+                    for i in 0..(imspection.image.num_channels()) {
                         if ui.button(format!(" {} ", i + 1)).clicked() {
-                            new_imspection = SingleImspection::new_with_took_channel(
-                                &imspection.image,
-                                i,
-                                self.next_available_id(),
-                            )
-                            .ok();
+                            new_imspection = imspection
+                                .new_with_took_channel(i, self.next_available_id())
+                                .ok();
                         };
                     }
                 });
@@ -195,11 +539,104 @@ impl ImspectApp {
             self.imspections.push(imsp);
         }
     }
+    fn render_extract_alpha(&mut self, ui: &mut Ui, idx: usize) {
+        let imspection = &self.imspections[idx];
+        if !imspection.alpha {
+            return;
+        };
+        if ui.button("Extract alpha").clicked() {
+            if let Ok(new_imspection) = imspection.new_with_alpha(self.next_available_id()) {
+                self.imspections.push(new_imspection);
+            };
+        };
+    }
+    fn render_alpha_view(&mut self, ui: &mut Ui, idx: usize) {
+        let imspection = self
+            .imspections
+            .get_mut(idx)
+            .expect("Imspectction by index exists");
+        if !imspection.alpha {
+            return;
+        };
+        let previous = imspection.alpha_view;
+        ComboBox::from_id_salt(format!("alpha_view_{}", imspection.id))
+            .selected_text(format!("Alpha: {}", imspection.alpha_view))
+            .show_ui(ui, |ui| {
+                for view in [
+                    AlphaView::Composite,
+                    AlphaView::Straight,
+                    AlphaView::Premultiplied,
+                ] {
+                    ui.selectable_value(&mut imspection.alpha_view, view, view.to_string());
+                }
+            });
+        if imspection.alpha_view != previous {
+            imspection.need_rerender = true;
+        };
+    }
+    fn render_yuv_planes(&mut self, ui: &mut Ui, idx: usize) {
+        let imspection = &self.imspections[idx];
+        if imspection.yuv.is_none() {
+            return;
+        };
+        let mut new_imspection: Option<SingleImspection> = None;
+        ui.menu_button("Show plane", |ui| {
+            ui.horizontal_top(|ui| {
+                for plane in [YuvPlane::Y, YuvPlane::U, YuvPlane::V] {
+                    if ui.button(format!(" {} ", plane)).clicked() {
+                        new_imspection = imspection
+                            .new_with_yuv_plane(plane, self.next_available_id())
+                            .ok();
+                    };
+                }
+            });
+        });
+        if let Some(imsp) = new_imspection {
+            self.imspections.push(imsp);
+        }
+    }
+    fn render_demosaic(&mut self, ui: &mut Ui, idx: usize) {
+        let imspection = &self.imspections[idx];
+        if imspection.image.num_channels() != 1 {
+            return;
+        };
+        let mut new_imspection: Option<SingleImspection> = None;
+        ui.menu_button("Bayer", |ui| {
+            egui::Grid::new(format!("bayer_{}", imspection.id)).show(ui, |ui| {
+                for pattern in CFA_PATTERNS {
+                    ui.label(pattern.to_string());
+                    for method in [Demosaic::Bilinear, Demosaic::EdgeAware] {
+                        if ui.button(method.to_string()).clicked() {
+                            new_imspection = imspection
+                                .new_with_demosaic(*pattern, method, self.next_available_id())
+                                .ok();
+                            ui.close_menu();
+                        };
+                    }
+                    if ui
+                        .button("CFA")
+                        .on_hover_text("Tint every photosite with its filter color")
+                        .clicked()
+                    {
+                        new_imspection = imspection
+                            .new_with_colorized_cfa(*pattern, self.next_available_id())
+                            .ok();
+                        ui.close_menu();
+                    };
+                    ui.end_row();
+                }
+            });
+        });
+        if let Some(imsp) = new_imspection {
+            self.imspections.push(imsp);
+        }
+    }
     fn render_clone_imspection(&mut self, ui: &mut Ui, idx: usize) {
         if ui.button("Clone").clicked() {
             let imspection = &self.imspections[idx];
-            let new_imspection = imspection.clone_with_thr(self.next_available_id());
-            self.imspections.push(new_imspection);
+            if let Ok(new_imspection) = imspection.clone_with_thr(self.next_available_id()) {
+                self.imspections.push(new_imspection);
+            };
         }
     }
 
@@ -210,7 +647,8 @@ impl ImspectApp {
         idx: usize,
         outer_size: &Vec2,
     ) {
-        let img_count = self.imspections.len();
+        let shown_count = self.imspections.iter().filter(|imsp| imsp.shown).count();
+        let (columns, rows) = self.panel_layout.shape(shown_count);
         let full_width = outer_size.x;
         let full_height = outer_size.y;
 
@@ -219,8 +657,8 @@ impl ImspectApp {
         egui::Resize::default()
             .id_salt(id)
             .default_size(Vec2::new(
-                (full_width / img_count as f32).max(full_width / 5.) - 5.,
-                full_height - 5.,
+                (full_width / columns as f32).max(full_width / 5.) - 5.,
+                (full_height / rows as f32).max(full_height / 5.) - 5.,
             ))
             .max_size(Vec2::new(full_width - 5., full_height - 2.))
             .show(ui, |ui| {
@@ -228,9 +666,12 @@ impl ImspectApp {
                 ui.with_layout(Layout::top_down(Align::LEFT), |ui| {
                     let inner_width = ui.available_width();
 
+                    let title = self.imspections[idx].title.clone();
                     Sides::new().show(
                         ui,
-                        |_| {},
+                        |ui| {
+                            ui.add(Label::new(title).truncate());
+                        },
                         |ui| {
                             if ui.small_button("X").clicked() {
                                 self.imspections
@@ -246,12 +687,42 @@ impl ImspectApp {
                         .get_mut(idx)
                         .expect("single imspection struct");
 
-                    let w = imspection.image.width();
-                    let h = imspection.image.height();
+                    // paged arrays are plotted in array pixels, their image is an overview
+                    let (w, h, step) = match &imspection.paged {
+                        Some(view) => (view.file.width, view.file.height, view.step),
+                        None => (imspection.image.width(), imspection.image.height(), 1),
+                    };
 
-                    prepare_texture(ctx, imspection);
-                    if let Some(texture) = &imspection.texture {
-                        Plot::new(format!("plot_{}", imspection.id))
+                    prepare_texture(ctx, imspection, self.background);
+                    let [shown_w, shown_h] = match &imspection.tiles {
+                        Some(tiles) => tiles.overview_extent(),
+                        None => [
+                            (imspection.image.width() * step) as f32,
+                            (imspection.image.height() * step) as f32,
+                        ],
+                    };
+                    // finer parts drawn over the texture, as [x, y, width, height]
+                    let mut overlays = imspection
+                        .tiles
+                        .as_ref()
+                        .map(|tiles| tiles.shown_tiles())
+                        .unwrap_or_default();
+                    if let Some((region, texture)) = imspection
+                        .paged
+                        .as_ref()
+                        .and_then(|view| view.detail.as_ref())
+                    {
+                        let size = region.sampled_size();
+                        let rect = [
+                            region.x as f32,
+                            region.y as f32,
+                            (size.width * region.step) as f32,
+                            (size.height * region.step) as f32,
+                        ];
+                        overlays.push((rect, texture.id()));
+                    };
+                    let visible = if let Some(texture) = &imspection.texture {
+                        let response = Plot::new(format!("plot_{}", imspection.id))
                             .data_aspect(1.0)
                             .set_margin_fraction(Vec2::new(0., 0.))
                             .width(inner_width)
@@ -271,65 +742,251 @@ impl ImspectApp {
                                     return format!("({}, {})\n", x, y);
                                 }
 
-                                match &imspection.image {
-                                    ImageKind::OneChannel(img) => {
-                                        let data = img.get([y as usize, x as usize, 0]);
-                                        match data {
-                                            Some(d) => format!("[{}]\n({}, {})\n", d, x, y),
-                                            None => format!("({}, {})\n", x, y),
+                                // a paged array is read at the pixel itself
+                                let sampled;
+                                let (image, px, py) = match &imspection.paged {
+                                    Some(view) => match view.file.pixel(x as usize, y as usize) {
+                                        Some(pixel) => {
+                                            sampled = pixel;
+                                            (&sampled, 0, 0)
                                         }
+                                        None => return format!("({}, {})\n", x, y),
+                                    },
+                                    None => (&imspection.image, x as usize, y as usize),
+                                };
+                                let alpha = match image.pixel_alpha(px, py) {
+                                    Some(alpha) if imspection.alpha => {
+                                        format!("\nalpha {:.3}", alpha)
                                     }
-                                    ImageKind::ThreeChannel(img) => {
-                                        // Collect data from all channels and ensure they're valid
-                                        let data: Option<Vec<&u8>> = (0..img.num_channels())
-                                            .map(|i| img.get([y as usize, x as usize, i]))
-                                            .collect();
-
-                                        // Format based on presence of valid data
-                                        match data {
-                                            Some(values) => {
-                                                format!("{:?}\n({}, {})\n", values, x, y)
-                                            }
-                                            None => format!("({}, {})\n", x, y),
-                                        }
+                                    _ => String::new(),
+                                };
+                                match image.pixel_label(px, py) {
+                                    Some(values) => {
+                                        format!("{}{}\n({}, {})\n", values, alpha, x, y)
                                     }
+                                    None => format!("({}, {})\n", x, y),
                                 }
                             })
                             .show(ui, |plot_ui| {
                                 plot_ui.image(PlotImage::new(
                                     texture.id(),
-                                    PlotPoint::new(w as f32 / 2., -(h as f32 / 2.)),
-                                    Vec2::new(w as f32, h as f32),
-                                ))
+                                    PlotPoint::new(shown_w / 2., -(shown_h / 2.)),
+                                    Vec2::new(shown_w, shown_h),
+                                ));
+                                for ([x, y, width, height], texture_id) in overlays {
+                                    plot_ui.image(PlotImage::new(
+                                        texture_id,
+                                        PlotPoint::new(x + width / 2., -(y + height / 2.)),
+                                        Vec2::new(width, height),
+                                    ));
+                                }
+                                plot_ui.plot_bounds()
                             });
+                        let (min, max) = (response.inner.min(), response.inner.max());
+                        Some([min[0], -max[1], max[0], -min[1]])
+                    } else {
+                        None
+                    };
+                    if let Some(visible) = visible {
+                        let screen_width = inner_width * ctx.pixels_per_point();
+                        prepare_detail(ctx, imspection, visible, screen_width, self.background);
+                        prepare_tiles(ctx, imspection, visible, screen_width, self.background);
                     };
 
+                    self.render_stack_controls(ui, idx);
                     ui.horizontal_top(|ui| {
                         self.render_color_conversions(ui, idx);
                         self.render_extract_channel(ui, idx);
+                        self.render_extract_alpha(ui, idx);
+                        self.render_yuv_planes(ui, idx);
+                        self.render_demosaic(ui, idx);
                         self.render_clone_imspection(ui, idx);
                     });
                     self.render_thresholding(ui, idx);
+                    self.render_display_range(ui, idx);
+                    self.render_alpha_view(ui, idx);
                 });
             });
     }
 
     fn render_central_panel(&mut self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
-            let img_count = self.imspections.len();
+            let shown: Vec<usize> = (0..self.imspections.len())
+                .filter(|&idx| self.imspections[idx].shown)
+                .collect();
             let outer_size = ui.available_size();
             ctx.style_mut(|style| {
                 style.spacing.scroll = ScrollStyle::thin();
             });
             egui::ScrollArea::both()
                 .id_salt("Main scroll area")
+                .show(ui, |ui| match self.panel_layout {
+                    PanelLayout::Row => {
+                        ui.horizontal_top(|ui| {
+                            for &idx in &shown {
+                                self.render_single_imspection(ctx, ui, idx, &outer_size);
+                            }
+                        });
+                    }
+                    PanelLayout::Column => {
+                        ui.vertical(|ui| {
+                            for &idx in &shown {
+                                self.render_single_imspection(ctx, ui, idx, &outer_size);
+                            }
+                        });
+                    }
+                    PanelLayout::Grid => {
+                        let (columns, _) = self.panel_layout.shape(shown.len());
+                        egui::Grid::new("Panels grid").show(ui, |ui| {
+                            for (i, &idx) in shown.iter().enumerate() {
+                                self.render_single_imspection(ctx, ui, idx, &outer_size);
+                                if (i + 1) % columns == 0 {
+                                    ui.end_row();
+                                };
+                            }
+                        });
+                    }
+                });
+        });
+    }
+    /// Small previews of every image, clicking one shows or hides its panel.
+    fn render_thumbnail_strip(&mut self, ctx: &egui::Context) {
+        if self.imspections.len() <= THUMBNAIL_STRIP_MIN {
+            return;
+        };
+        egui::TopBottomPanel::bottom("Thumbnails").show(ctx, |ui| {
+            egui::ScrollArea::horizontal()
+                .id_salt("Thumbnails scroll area")
                 .show(ui, |ui| {
-                    ui.horizontal_top(|ui| {
-                        for idx in 0..img_count {
-                            self.render_single_imspection(ctx, ui, idx, &outer_size);
+                    ui.horizontal(|ui| {
+                        for imspection in self.imspections.iter_mut() {
+                            let (rect, response) = ui.allocate_exact_size(
+                                Vec2::splat(THUMBNAIL_SIZE as f32),
+                                Sense::click(),
+                            );
+                            // textures are only made for thumbnails scrolled into view
+                            if ui.is_rect_visible(rect) {
+                                prepare_texture(ctx, imspection, self.background);
+                                paint_thumbnail(
+                                    ui,
+                                    rect,
+                                    imspection.texture.as_ref(),
+                                    imspection.shown,
+                                );
+                            };
+                            if response.on_hover_text(&imspection.title).clicked() {
+                                imspection.shown = !imspection.shown;
+                            };
+                        }
+                    });
+                });
+        });
+    }
+    fn render_menu(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::top("Menu").show(ctx, |ui| {
+            egui::menu::bar(ui, |ui| {
+                ui.menu_button("File", |ui| {
+                    if ui.button("Open raw buffer…").clicked() {
+                        self.raw_dialog_open = true;
+                        ui.close_menu();
+                    };
+                });
+                ui.menu_button("View", |ui| self.render_background_choice(ui));
+                self.render_array_picker(ui);
+            });
+        });
+        if let Some(img) = self.raw_dialog.show(ctx, &mut self.raw_dialog_open) {
+            self.add_image(img);
+        };
+    }
+    /// Checkboxes that show or hide the arrays of every `.npz` archive.
+    fn render_array_picker(&mut self, ui: &mut Ui) {
+        let mut archives: Vec<(PathBuf, Vec<usize>)> = Vec::new();
+        for (idx, imspection) in self.imspections.iter().enumerate() {
+            let Some(archive) = archive_of(imspection) else {
+                continue;
+            };
+            match archives.iter_mut().find(|(path, _)| path == archive) {
+                Some((_, members)) => members.push(idx),
+                None => archives.push((archive.to_owned(), vec![idx])),
+            };
+        }
+        if archives.is_empty() {
+            return;
+        };
+
+        ui.menu_button("Arrays", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Filter");
+                ui.text_edit_singleline(&mut self.array_filter);
+            });
+            let filter = self.array_filter.to_lowercase();
+            for (archive, members) in archives {
+                let listed: Vec<usize> = members
+                    .into_iter()
+                    .filter(|&idx| self.imspections[idx].title.to_lowercase().contains(&filter))
+                    .collect();
+                ui.menu_button(file_title(&archive), |ui| {
+                    ui.horizontal(|ui| {
+                        for (label, shown) in [("All", true), ("None", false)] {
+                            if ui.button(label).clicked() {
+                                for &idx in &listed {
+                                    self.imspections[idx].shown = shown;
+                                }
+                            };
                         }
                     });
+                    egui::ScrollArea::vertical()
+                        .id_salt("Arrays scroll area")
+                        .max_height(400.)
+                        .show(ui, |ui| {
+                            for &idx in &listed {
+                                let imspection = &mut self.imspections[idx];
+                                ui.checkbox(&mut imspection.shown, imspection.title.as_str());
+                            }
+                        });
                 });
+            }
+        });
+    }
+    /// Background of transparent images, every one of them is redrawn when it changes.
+    fn render_background_choice(&mut self, ui: &mut Ui) {
+        let previous = self.background;
+        ui.label("Transparency background");
+        ui.radio_value(
+            &mut self.background,
+            Background::Checkerboard,
+            "Checkerboard",
+        );
+        ui.horizontal(|ui| {
+            let solid = matches!(self.background, Background::Solid(_));
+            if ui.radio(solid, "Solid").clicked() && !solid {
+                self.background = Background::Solid([0, 0, 0]);
+            };
+            if let Background::Solid(rgb) = &mut self.background {
+                ui.color_edit_button_srgb(rgb);
+            };
+        });
+        if self.background != previous {
+            for imspection in self.imspections.iter_mut().filter(|imsp| imsp.alpha) {
+                imspection.need_rerender = true;
+            }
+        };
+    }
+    fn render_load_errors(&mut self, ctx: &egui::Context) {
+        if self.load_errors.is_empty() {
+            return;
+        };
+        egui::TopBottomPanel::bottom("Load errors").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.strong(format!("Failed to load {} file(s)", self.load_errors.len()));
+                if ui.button("Dismiss").clicked() {
+                    self.load_errors.clear();
+                };
+            });
+            for error in &self.load_errors {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
         });
     }
     fn remove_marked_imspections(&mut self) {
@@ -343,8 +1000,26 @@ impl ImspectApp {
 
 impl eframe::App for ImspectApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.receive_incoming(ctx);
+        self.receive_gallery(ctx);
+        self.receive_reloads();
+        self.receive_overviews(ctx);
         self.remove_marked_imspections();
+        self.advance_stacks(ctx);
 
+        self.render_menu(ctx);
+        self.render_load_errors(ctx);
+        self.render_thumbnail_strip(ctx);
+        self.render_gallery(ctx);
         self.render_central_panel(ctx);
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if self.report_on_exit {
+            let mut stdout = io::stdout().lock();
+            if let Err(err) = ipc::write_results(&mut stdout, &self.imspections) {
+                eprintln!("Failed to send the images back: {:#}", err);
+            };
+        }
+    }
 }
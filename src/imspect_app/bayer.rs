@@ -0,0 +1,363 @@
+//! Demosaicing of raw Bayer mosaics from camera sensors.
+
+use std::fmt;
+
+use kornia::image::ImageError;
+
+use crate::imspect_app::image_kind::{ChannelsKind, DType, ImageKind, Pixel};
+
+/// Colors of the top-left 2x2 block of the color filter array.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CfaPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+pub const CFA_PATTERNS: &[CfaPattern] = &[
+    CfaPattern::Rggb,
+    CfaPattern::Bggr,
+    CfaPattern::Grbg,
+    CfaPattern::Gbrg,
+];
+
+impl CfaPattern {
+    /// RGB channel of the photosite at column `x` and row `y`.
+    fn channel(&self, x: usize, y: usize) -> usize {
+        let block = match self {
+            CfaPattern::Rggb => [[0, 1], [1, 2]],
+            CfaPattern::Bggr => [[2, 1], [1, 0]],
+            CfaPattern::Grbg => [[1, 0], [2, 1]],
+            CfaPattern::Gbrg => [[1, 2], [0, 1]],
+        };
+        block[y % 2][x % 2]
+    }
+}
+
+impl fmt::Display for CfaPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_uppercase())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Demosaic {
+    /// Average of the nearest photosites of each color.
+    Bilinear,
+    /// Green is interpolated along the flatter direction, red and blue follow
+    /// the green differences, which avoids most color fringes on edges.
+    EdgeAware,
+}
+
+impl fmt::Display for Demosaic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Demosaic::Bilinear => write!(f, "bilinear"),
+            Demosaic::EdgeAware => write!(f, "edge-aware"),
+        }
+    }
+}
+
+/// A one-channel mosaic as `f32` values with its size.
+struct Mosaic {
+    values: Vec<f32>,
+    width: usize,
+    height: usize,
+    pattern: CfaPattern,
+}
+
+impl Mosaic {
+    fn new<T: Pixel>(img: &ChannelsKind<T>, pattern: CfaPattern) -> Result<Self, ImageError> {
+        if img.num_channels() != 1 {
+            return Err(ImageError::InvalidChannelShape(img.num_channels(), 1));
+        };
+        let size = img.size();
+        Ok(Self {
+            values: img.as_slice().iter().map(|v| v.to_f32()).collect(),
+            width: size.width,
+            height: size.height,
+            pattern,
+        })
+    }
+
+    /// Neighbours within the 3x3 window that lie inside the image.
+    fn neighbours(&self, x: usize, y: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let xs = x.saturating_sub(1)..=(x + 1).min(self.width - 1);
+        let ys = y.saturating_sub(1)..=(y + 1).min(self.height - 1);
+        ys.flat_map(move |ny| xs.clone().map(move |nx| (nx, ny)))
+            .filter(move |&(nx, ny)| (nx, ny) != (x, y))
+    }
+
+    /// Mean of `value` over the neighbours of color `channel`.
+    fn mean_of(&self, x: usize, y: usize, channel: usize, value: impl Fn(usize) -> f32) -> f32 {
+        let (sum, count) = self
+            .neighbours(x, y)
+            .filter(|&(nx, ny)| self.pattern.channel(nx, ny) == channel)
+            .fold((0., 0), |(sum, count), (nx, ny)| {
+                (sum + value(ny * self.width + nx), count + 1)
+            });
+        if count == 0 {
+            0.
+        } else {
+            sum / count as f32
+        }
+    }
+
+    fn bilinear(&self) -> Vec<f32> {
+        let mut rgb = Vec::with_capacity(self.values.len() * 3);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let site = self.pattern.channel(x, y);
+                for channel in 0..3 {
+                    rgb.push(if channel == site {
+                        self.values[y * self.width + x]
+                    } else {
+                        self.mean_of(x, y, channel, |i| self.values[i])
+                    });
+                }
+            }
+        }
+        rgb
+    }
+
+    /// Green of a red or blue photosite, averaged along the direction with the smaller gradient.
+    fn directional_green(&self, x: usize, y: usize) -> f32 {
+        let at = |x: usize, y: usize| self.values[y * self.width + x];
+        let horizontal = (x > 0 && x + 1 < self.width).then(|| (at(x - 1, y), at(x + 1, y)));
+        let vertical = (y > 0 && y + 1 < self.height).then(|| (at(x, y - 1), at(x, y + 1)));
+        match (horizontal, vertical) {
+            (Some((left, right)), Some((up, down))) => {
+                let dh = (left - right).abs();
+                let dv = (up - down).abs();
+                if dh < dv {
+                    (left + right) / 2.
+                } else if dv < dh {
+                    (up + down) / 2.
+                } else {
+                    (left + right + up + down) / 4.
+                }
+            }
+            _ => self.mean_of(x, y, 1, |i| self.values[i]),
+        }
+    }
+
+    fn edge_aware(&self) -> Vec<f32> {
+        let green: Vec<f32> = (0..self.height)
+            .flat_map(|y| (0..self.width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                if self.pattern.channel(x, y) == 1 {
+                    self.values[y * self.width + x]
+                } else {
+                    self.directional_green(x, y)
+                }
+            })
+            .collect();
+
+        let mut rgb = Vec::with_capacity(self.values.len() * 3);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let site = self.pattern.channel(x, y);
+                for channel in 0..3 {
+                    rgb.push(if channel == 1 {
+                        green[i]
+                    } else if channel == site {
+                        self.values[i]
+                    } else {
+                        // red and blue keep their difference to green from the nearest sites
+                        green[i] + self.mean_of(x, y, channel, |n| self.values[n] - green[n])
+                    });
+                }
+            }
+        }
+        rgb
+    }
+
+    /// Every photosite in its filter color, the other channels black.
+    fn colorized(&self) -> Vec<f32> {
+        let mut rgb = vec![0.; self.values.len() * 3];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                rgb[i * 3 + self.pattern.channel(x, y)] = self.values[i];
+            }
+        }
+        rgb
+    }
+}
+
+fn to_rgb<T: Pixel>(
+    img: &ChannelsKind<T>,
+    pattern: CfaPattern,
+    make: impl Fn(&Mosaic) -> Vec<f32>,
+) -> Result<ChannelsKind<T>, ImageError> {
+    let mosaic = Mosaic::new(img, pattern)?;
+    if mosaic.values.is_empty() {
+        // nothing to interpolate, and `Mosaic::neighbours` needs a photosite
+        return ChannelsKind::new(img.size(), 3, Vec::new());
+    };
+    let rgb = make(&mosaic).into_iter().map(T::from_f32).collect();
+    ChannelsKind::new(img.size(), 3, rgb)
+}
+
+/// Applies `make` to a mosaic of any pixel type.
+/// `interpolated` values are no longer of the source integer type.
+fn map_mosaic(
+    image: &ImageKind,
+    pattern: CfaPattern,
+    interpolated: bool,
+    make: impl Fn(&Mosaic) -> Vec<f32>,
+) -> Result<ImageKind, ImageError> {
+    Ok(match image {
+        ImageKind::U8(img) => ImageKind::U8(to_rgb(img, pattern, make)?),
+        ImageKind::U16(img) => ImageKind::U16(to_rgb(img, pattern, make)?),
+        ImageKind::F32(img, dtype) => {
+            let dtype = if interpolated { DType::F32 } else { *dtype };
+            ImageKind::F32(to_rgb(img, pattern, make)?, dtype)
+        }
+    })
+}
+
+/// Interpolates the two missing colors of every photosite.
+pub fn demosaic(
+    image: &ImageKind,
+    pattern: CfaPattern,
+    method: Demosaic,
+) -> Result<ImageKind, ImageError> {
+    map_mosaic(image, pattern, true, |mosaic| match method {
+        Demosaic::Bilinear => mosaic.bilinear(),
+        Demosaic::EdgeAware => mosaic.edge_aware(),
+    })
+}
+
+/// Tints every photosite with its filter color.
+pub fn colorize_cfa(image: &ImageKind, pattern: CfaPattern) -> Result<ImageKind, ImageError> {
+    map_mosaic(image, pattern, false, Mosaic::colorized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kornia::image::ImageSize;
+
+    fn mosaic(width: usize, height: usize, values: Vec<f32>) -> ImageKind {
+        let size = ImageSize { width, height };
+        ImageKind::F32(ChannelsKind::new(size, 1, values).unwrap(), DType::F32)
+    }
+
+    fn values(image: &ImageKind) -> Vec<f32> {
+        match image {
+            ImageKind::F32(img, _) => img.as_slice().to_vec(),
+            _ => panic!("expected a float image"),
+        }
+    }
+
+    /// RGB value of the pixel at column `x` and row `y`.
+    fn rgb_at(rgb: &[f32], width: usize, x: usize, y: usize) -> [f32; 3] {
+        let i = (y * width + x) * 3;
+        [rgb[i], rgb[i + 1], rgb[i + 2]]
+    }
+
+    #[test]
+    fn flat_color_is_kept_for_every_pattern() {
+        let color = [10., 20., 30.];
+        for &pattern in CFA_PATTERNS {
+            let sites = (0..16)
+                .map(|i| color[pattern.channel(i % 4, i / 4)])
+                .collect();
+            let image = mosaic(4, 4, sites);
+            let flat: Vec<f32> = color.repeat(16);
+            for method in [Demosaic::Bilinear, Demosaic::EdgeAware] {
+                let rgb = values(&demosaic(&image, pattern, method).unwrap());
+                assert_eq!(rgb, flat, "{} {}", pattern, method);
+            }
+            let colorized = values(&colorize_cfa(&image, pattern).unwrap());
+            for i in 0..16 {
+                let channel = pattern.channel(i % 4, i / 4);
+                let mut expected = [0.; 3];
+                expected[channel] = color[channel];
+                assert_eq!(colorized[i * 3..i * 3 + 3], expected, "{}", pattern);
+            }
+        }
+    }
+
+    #[test]
+    fn patterns_start_with_their_first_color() {
+        assert_eq!(CfaPattern::Rggb.channel(0, 0), 0);
+        assert_eq!(CfaPattern::Bggr.channel(0, 0), 2);
+        assert_eq!(CfaPattern::Grbg.channel(1, 0), 0);
+        assert_eq!(CfaPattern::Gbrg.channel(1, 0), 2);
+        assert_eq!(CfaPattern::Rggb.channel(3, 3), 2);
+    }
+
+    #[test]
+    fn bilinear_2x2() {
+        // R G
+        // G B
+        let image = mosaic(2, 2, vec![100., 50., 30., 10.]);
+        let rgb = values(&demosaic(&image, CfaPattern::Rggb, Demosaic::Bilinear).unwrap());
+        assert_eq!(
+            rgb,
+            [100., 40., 10., 100., 50., 10., 100., 30., 10., 100., 40., 10.]
+        );
+    }
+
+    #[test]
+    fn edge_aware_2x2_follows_green_differences() {
+        let image = mosaic(2, 2, vec![100., 50., 30., 10.]);
+        let rgb = values(&demosaic(&image, CfaPattern::Rggb, Demosaic::EdgeAware).unwrap());
+        assert_eq!(
+            rgb,
+            [100., 40., 10., 110., 50., 20., 90., 30., 0., 100., 40., 10.]
+        );
+    }
+
+    #[test]
+    fn edge_aware_4x4_interpolates_along_edges() {
+        // a vertical edge between the dark left and the bright right half
+        let sites = (0..16).map(|i| if i % 4 < 2 { 0. } else { 100. }).collect();
+        let image = mosaic(4, 4, sites);
+        let bilinear = values(&demosaic(&image, CfaPattern::Rggb, Demosaic::Bilinear).unwrap());
+        let edge_aware = values(&demosaic(&image, CfaPattern::Rggb, Demosaic::EdgeAware).unwrap());
+        // red photosite right of the edge, blue photosite left of it
+        assert_eq!(rgb_at(&bilinear, 4, 2, 2)[1], 75.);
+        assert_eq!(rgb_at(&edge_aware, 4, 2, 2)[1], 100.);
+        assert_eq!(rgb_at(&bilinear, 4, 1, 1)[1], 25.);
+        assert_eq!(rgb_at(&edge_aware, 4, 1, 1)[1], 0.);
+        // away from the edge both agree
+        assert_eq!(rgb_at(&bilinear, 4, 0, 0), [0., 0., 0.]);
+        assert_eq!(rgb_at(&edge_aware, 4, 3, 3), [100., 100., 100.]);
+    }
+
+    #[test]
+    fn integer_mosaics_keep_their_type() {
+        let size = ImageSize {
+            width: 2,
+            height: 2,
+        };
+        let image = ImageKind::U8(ChannelsKind::new(size, 1, vec![200, 100, 60, 20]).unwrap());
+        let ImageKind::U8(rgb) = demosaic(&image, CfaPattern::Rggb, Demosaic::Bilinear).unwrap()
+        else {
+            panic!("expected an 8-bit image");
+        };
+        assert_eq!(rgb.pixel(1, 1), Some(&[200, 80, 20][..]));
+    }
+
+    #[test]
+    fn rejects_color_images_and_passes_empty_ones() {
+        let size = ImageSize {
+            width: 1,
+            height: 1,
+        };
+        let color = ImageKind::U8(ChannelsKind::new(size, 3, vec![0; 3]).unwrap());
+        assert!(demosaic(&color, CfaPattern::Rggb, Demosaic::Bilinear).is_err());
+
+        let empty = mosaic(0, 3, Vec::new());
+        for method in [Demosaic::Bilinear, Demosaic::EdgeAware] {
+            let rgb = demosaic(&empty, CfaPattern::Rggb, method).unwrap();
+            assert_eq!((rgb.width(), rgb.height(), rgb.num_channels()), (0, 3, 3));
+        }
+        assert!(colorize_cfa(&empty, CfaPattern::Bggr).is_ok());
+    }
+}
@@ -0,0 +1,208 @@
+//! Gallery of files that are only decoded when their thumbnail is scrolled into view.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use eframe::epaint::textures::TextureOptions;
+use eframe::epaint::{ColorImage, TextureHandle};
+
+use crate::imspect_app::image_kind::NamedImage;
+use crate::imspect_app::textures::thumbnail_image;
+use crate::input::{bgr_to_rgb, file_title, load_image};
+
+/// Decoded images kept in memory, so reopening a recent one doesn't read the file again.
+const DECODED_CACHE_SIZE: usize = 16;
+/// Thumbnail textures kept on the GPU.
+const THUMBNAIL_CACHE_SIZE: usize = 512;
+
+/// Least recently used entries are dropped first.
+struct Lru<T> {
+    capacity: usize,
+    items: VecDeque<(usize, T)>,
+}
+
+impl<T> Lru<T> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            items: VecDeque::with_capacity(capacity),
+        }
+    }
+    fn get(&mut self, key: usize) -> Option<&T> {
+        let pos = self.items.iter().position(|(k, _)| *k == key)?;
+        let item = self.items.remove(pos)?;
+        self.items.push_back(item);
+        self.items.back().map(|(_, value)| value)
+    }
+    fn insert(&mut self, key: usize, value: T) {
+        self.items.retain(|(k, _)| *k != key);
+        self.items.push_back((key, value));
+        if self.items.len() > self.capacity {
+            self.items.pop_front();
+        };
+    }
+}
+
+enum EntryState {
+    Idle,
+    Requested,
+    Failed(String),
+}
+
+pub struct GalleryEntry {
+    pub title: String,
+    path: PathBuf,
+    state: EntryState,
+}
+
+struct Decoded {
+    idx: usize,
+    result: Result<(NamedImage, ColorImage), String>,
+}
+
+/// What the gallery produced since the last frame.
+#[derive(Default)]
+pub struct GalleryUpdate {
+    /// Images the user asked to inspect.
+    pub open: Vec<NamedImage>,
+    pub errors: Vec<String>,
+}
+
+pub struct Gallery {
+    pub entries: Vec<GalleryEntry>,
+    requests: Sender<(usize, PathBuf)>,
+    decoded: Receiver<Decoded>,
+    images: Lru<NamedImage>,
+    thumbnails: Lru<TextureHandle>,
+    /// Entries to open as panels once decoded.
+    pending_open: Vec<usize>,
+}
+
+impl Gallery {
+    /// `bgr` swaps the channels of BGR(A) images once decoded.
+    pub fn new(ctx: egui::Context, paths: Vec<PathBuf>, bgr: bool) -> Self {
+        let (requests, request_receiver) = mpsc::channel();
+        let (decoded_sender, decoded) = mpsc::channel();
+        thread::spawn(move || decode_requests(ctx, request_receiver, decoded_sender, bgr));
+
+        let entries = paths
+            .into_iter()
+            .map(|path| GalleryEntry {
+                title: file_title(&path),
+                path,
+                state: EntryState::Idle,
+            })
+            .collect();
+        Self {
+            entries,
+            requests,
+            decoded,
+            images: Lru::new(DECODED_CACHE_SIZE),
+            thumbnails: Lru::new(THUMBNAIL_CACHE_SIZE),
+            pending_open: Vec::new(),
+        }
+    }
+
+    fn request(&mut self, idx: usize) {
+        let entry = &mut self.entries[idx];
+        if matches!(entry.state, EntryState::Idle) {
+            entry.state = EntryState::Requested;
+            let _ = self.requests.send((idx, entry.path.clone()));
+        };
+    }
+
+    /// Thumbnail of a visible entry, decoding the file if it isn't cached.
+    pub fn thumbnail(&mut self, idx: usize) -> Option<&TextureHandle> {
+        if self.thumbnails.get(idx).is_none() {
+            self.request(idx);
+            return None;
+        };
+        self.thumbnails.get(idx)
+    }
+
+    pub fn error(&self, idx: usize) -> Option<&str> {
+        match &self.entries[idx].state {
+            EntryState::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// Opens the entry as a panel, right away if its image is still cached.
+    pub fn open(&mut self, idx: usize) -> Option<NamedImage> {
+        if let Some(img) = self.images.get(idx) {
+            return Some(img.clone());
+        };
+        if matches!(self.entries[idx].state, EntryState::Failed(_)) {
+            return None;
+        };
+        if !self.pending_open.contains(&idx) {
+            self.pending_open.push(idx);
+        };
+        self.request(idx);
+        None
+    }
+
+    /// Takes the images decoded in the background.
+    pub fn receive(&mut self, ctx: &egui::Context) -> GalleryUpdate {
+        let mut update = GalleryUpdate::default();
+        for Decoded { idx, result } in self.decoded.try_iter().collect::<Vec<_>>() {
+            let entry = &mut self.entries[idx];
+            match result {
+                Ok((img, thumbnail)) => {
+                    entry.state = EntryState::Idle;
+                    let texture = ctx.load_texture(
+                        format!("thumbnail_{}", idx),
+                        thumbnail,
+                        TextureOptions::LINEAR,
+                    );
+                    self.thumbnails.insert(idx, texture);
+                    if let Some(pos) = self.pending_open.iter().position(|&i| i == idx) {
+                        self.pending_open.remove(pos);
+                        update.open.push(img.clone());
+                    };
+                    self.images.insert(idx, img);
+                }
+                Err(error) => {
+                    update
+                        .errors
+                        .push(format!("{}: {}", entry.path.display(), error));
+                    entry.state = EntryState::Failed(error);
+                    self.pending_open.retain(|&i| i != idx);
+                }
+            }
+        }
+        update
+    }
+}
+
+/// Decodes the latest requests first, the ones for thumbnails the user is looking at.
+fn decode_requests(
+    ctx: egui::Context,
+    requests: Receiver<(usize, PathBuf)>,
+    decoded: Sender<Decoded>,
+    bgr: bool,
+) {
+    let mut stack = Vec::new();
+    while let Ok(request) = requests.recv() {
+        stack.push(request);
+        stack.extend(requests.try_iter());
+        while let Some((idx, path)) = stack.pop() {
+            let result = decode(&path, bgr);
+            if decoded.send(Decoded { idx, result }).is_err() {
+                // the window is closed
+                return;
+            };
+            ctx.request_repaint();
+            stack.extend(requests.try_iter());
+        }
+    }
+}
+
+fn decode(path: &Path, bgr: bool) -> Result<(NamedImage, ColorImage), String> {
+    let img = load_image(path).map_err(|err| format!("{:#}", err))?;
+    let img = if bgr { bgr_to_rgb(img) } else { img };
+    let thumbnail = thumbnail_image(&img.image, img.alpha);
+    Ok((img, thumbnail))
+}
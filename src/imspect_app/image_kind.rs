@@ -0,0 +1,536 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use kornia::image::{Image, ImageError, ImageSize};
+use kornia::imgproc::color;
+
+use crate::paged::{PagedNpy, Region};
+use crate::yuv::YuvFrame;
+
+/// Storage type of image pixels.
+pub trait Pixel: Copy + Default + PartialOrd + fmt::Debug + Send + Sync + 'static {
+    /// Value written by binary thresholds.
+    const MAX_VALUE: Self;
+
+    fn to_f32(self) -> f32;
+
+    /// Saturating conversion, `NaN` becomes zero for integer types.
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Pixel for u8 {
+    const MAX_VALUE: Self = u8::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn from_f32(value: f32) -> Self {
+        value as u8
+    }
+}
+
+impl Pixel for u16 {
+    const MAX_VALUE: Self = u16::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn from_f32(value: f32) -> Self {
+        value as u16
+    }
+}
+
+impl Pixel for f32 {
+    const MAX_VALUE: Self = 1.;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+/// Min and max of the finite values.
+pub fn data_range<T: Pixel>(data: &[T]) -> (f32, f32) {
+    data.iter()
+        .map(|v| v.to_f32())
+        .filter(|v| v.is_finite())
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(v), hi.max(v))
+        })
+}
+
+#[derive(Clone)]
+pub enum ChannelsKind<T> {
+    One(Image<T, 1>),
+    Two(Image<T, 2>),
+    Three(Image<T, 3>),
+    Four(Image<T, 4>),
+}
+
+impl<T: Pixel> ChannelsKind<T> {
+    /// Wraps interleaved (HWC) pixel data.
+    pub fn new(size: ImageSize, channels: usize, data: Vec<T>) -> Result<Self, ImageError> {
+        match channels {
+            1 => Ok(Self::One(Image::new(size, data)?)),
+            2 => Ok(Self::Two(Image::new(size, data)?)),
+            3 => Ok(Self::Three(Image::new(size, data)?)),
+            4 => Ok(Self::Four(Image::new(size, data)?)),
+            _ => Err(ImageError::InvalidChannelShape(channels, 4)),
+        }
+    }
+    pub fn num_channels(&self) -> usize {
+        match self {
+            Self::One(_) => 1,
+            Self::Two(_) => 2,
+            Self::Three(_) => 3,
+            Self::Four(_) => 4,
+        }
+    }
+    pub fn size(&self) -> ImageSize {
+        match self {
+            Self::One(img) => img.size(),
+            Self::Two(img) => img.size(),
+            Self::Three(img) => img.size(),
+            Self::Four(img) => img.size(),
+        }
+    }
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            Self::One(img) => img.as_slice(),
+            Self::Two(img) => img.as_slice(),
+            Self::Three(img) => img.as_slice(),
+            Self::Four(img) => img.as_slice(),
+        }
+    }
+    /// All channel values of the pixel at column `x` and row `y`.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<&[T]> {
+        let size = self.size();
+        if x >= size.width || y >= size.height {
+            return None;
+        }
+        let c = self.num_channels();
+        let start = (y * size.width + x) * c;
+        self.as_slice().get(start..start + c)
+    }
+    /// Every `step`-th pixel of `region` along both axes, the region must lie within the image.
+    pub fn sample(&self, region: &Region) -> Result<Self, ImageError> {
+        let width = self.size().width;
+        let c = self.num_channels();
+        let data = self.as_slice();
+        let size = region.sampled_size();
+        let values = (0..size.height)
+            .flat_map(|row| {
+                let start = ((region.y + row * region.step) * width + region.x) * c;
+                (0..size.width).flat_map(move |column| {
+                    let px = start + column * region.step * c;
+                    &data[px..px + c]
+                })
+            })
+            .copied()
+            .collect();
+        Self::new(size, c, values)
+    }
+    pub fn channel(&self, channel_i: usize) -> Result<Image<T, 1>, ImageError> {
+        match self {
+            Self::One(img) => img.channel(channel_i),
+            Self::Two(img) => img.channel(channel_i),
+            Self::Three(img) => img.channel(channel_i),
+            Self::Four(img) => img.channel(channel_i),
+        }
+    }
+
+    /// Opacity in `0..=1` of an alpha value.
+    fn opacity(value: T) -> f32 {
+        (value.to_f32() / T::MAX_VALUE.to_f32()).clamp(0., 1.)
+    }
+
+    /// Opacity of every pixel in `0..=1`, taken from the last channel.
+    fn alpha(&self) -> Vec<f32> {
+        let c = self.num_channels();
+        self.as_slice()
+            .chunks_exact(c)
+            .map(|px| Self::opacity(px[c - 1]))
+            .collect()
+    }
+
+    fn pixel_alpha(&self, x: usize, y: usize) -> Option<f32> {
+        self.pixel(x, y)?.last().map(|&value| Self::opacity(value))
+    }
+
+    /// Builds an image from the given channels of every pixel, in that order.
+    fn reorder(&self, order: &[usize]) -> Result<Self, ImageError> {
+        let data = self
+            .as_slice()
+            .chunks_exact(self.num_channels())
+            .flat_map(|px| order.iter().map(move |&i| px[i]))
+            .collect();
+        Self::new(self.size(), order.len(), data)
+    }
+
+    fn to_f32_rgb(&self) -> Result<Image<f32, 3>, ImageError> {
+        match self {
+            Self::Three(img) => Image::new(
+                img.size(),
+                img.as_slice().iter().map(|v| v.to_f32()).collect(),
+            ),
+            _ => Err(ImageError::InvalidChannelShape(self.num_channels(), 3)),
+        }
+    }
+
+    pub fn change_color(&self, color: ColorSpaceChange) -> Result<Self, ImageError> {
+        let expected = color.source_channels();
+        if self.num_channels() != expected {
+            return Err(ImageError::InvalidChannelShape(
+                self.num_channels(),
+                expected,
+            ));
+        }
+        match color {
+            ColorSpaceChange::GRAY2RGB => self.reorder(&[0, 0, 0]),
+            ColorSpaceChange::BGR2RGB => self.reorder(&[2, 1, 0]),
+            ColorSpaceChange::BGRA2RGBA => self.reorder(&[2, 1, 0, 3]),
+            ColorSpaceChange::RGBA2RGB => self.reorder(&[0, 1, 2]),
+            ColorSpaceChange::RGB2GRAY => {
+                let mut new_img = Image::<f32, 1>::from_size_val(self.size(), 0.)?;
+                color::gray_from_rgb(&self.to_f32_rgb()?, &mut new_img)?;
+                Self::new(
+                    self.size(),
+                    1,
+                    new_img.as_slice().iter().map(|v| T::from_f32(*v)).collect(),
+                )
+            }
+            ColorSpaceChange::RGB2HSV => {
+                // kornia expects and returns values in `0..=255`, the other types are
+                // rescaled from and back to their full range so the channels share a scale
+                let scale = T::MAX_VALUE.to_f32() / 255.;
+                let rgb = self.to_f32_rgb()?;
+                let rgb = Image::new(
+                    rgb.size(),
+                    rgb.as_slice().iter().map(|v| v / scale).collect(),
+                )?;
+                let mut new_img = Image::<f32, 3>::from_size_val(self.size(), 0.)?;
+                color::hsv_from_rgb(&rgb, &mut new_img)?;
+                Self::new(
+                    self.size(),
+                    3,
+                    new_img
+                        .as_slice()
+                        .iter()
+                        .map(|v| T::from_f32(v * scale))
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// Largest magnitude up to which every integer is exact in an `f32`, `2^24`.
+const EXACT_F32_INTEGER: f32 = (1 << f32::MANTISSA_DIGITS) as f32;
+
+/// Element type of the array an image was loaded from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DType {
+    Bool,
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl DType {
+    /// Full value range of the type, used by `DisplayRange::Dtype`.
+    /// Floats are assumed to be normalized to `0..1`.
+    pub fn range(&self) -> (f32, f32) {
+        match self {
+            DType::Bool => (0., 1.),
+            DType::U8 => (0., u8::MAX as f32),
+            DType::I8 => (i8::MIN as f32, i8::MAX as f32),
+            DType::U16 => (0., u16::MAX as f32),
+            DType::I16 => (i16::MIN as f32, i16::MAX as f32),
+            DType::U32 => (0., u32::MAX as f32),
+            DType::I32 => (i32::MIN as f32, i32::MAX as f32),
+            DType::I64 => (i64::MIN as f32, i64::MAX as f32),
+            DType::F32 | DType::F64 => (0., 1.),
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        !matches!(self, DType::F32 | DType::F64)
+    }
+
+    /// Formats a pixel value the way it looked in the source array.
+    /// Integers beyond `2^24` may have lost precision when widened, they are marked with `~`.
+    pub fn format_value(&self, value: f32) -> String {
+        match self {
+            DType::Bool => (value != 0.).to_string(),
+            DType::F32 | DType::F64 => value.to_string(),
+            _ if value.abs() > EXACT_F32_INTEGER => format!("~{}", value as i64),
+            _ => format!("{}", value as i64),
+        }
+    }
+}
+
+impl fmt::Display for DType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DType::Bool => "bool",
+            DType::U8 => "uint8",
+            DType::I8 => "int8",
+            DType::U16 => "uint16",
+            DType::I16 => "int16",
+            DType::U32 => "uint32",
+            DType::I32 => "int32",
+            DType::I64 => "int64",
+            DType::F32 => "float32",
+            DType::F64 => "float64",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone)]
+pub enum ImageKind {
+    U8(ChannelsKind<u8>),
+    U16(ChannelsKind<u16>),
+    /// Floating point data. Other dtypes that don't fit `u8`/`u16` are widened
+    /// here too, remembering the source dtype.
+    F32(ChannelsKind<f32>, DType),
+}
+
+/// Evaluates `$body` with `$img` bound to the inner [`ChannelsKind`], whatever the pixel type.
+macro_rules! with_channels {
+    ($image:expr, $img:ident => $body:expr) => {
+        match $image {
+            ImageKind::U8($img) => $body,
+            ImageKind::U16($img) => $body,
+            ImageKind::F32($img, _) => $body,
+        }
+    };
+}
+
+impl ImageKind {
+    pub fn num_channels(&self) -> usize {
+        with_channels!(self, img => img.num_channels())
+    }
+    pub fn width(&self) -> usize {
+        with_channels!(self, img => img.size().width)
+    }
+    pub fn height(&self) -> usize {
+        with_channels!(self, img => img.size().height)
+    }
+    pub fn dtype(&self) -> DType {
+        match self {
+            ImageKind::U8(_) => DType::U8,
+            ImageKind::U16(_) => DType::U16,
+            ImageKind::F32(_, dtype) => *dtype,
+        }
+    }
+
+    /// Opacity of every pixel in `0..=1` from the last channel of gray+alpha and RGBA images.
+    pub fn alpha(&self) -> Option<Vec<f32>> {
+        if !matches!(self.num_channels(), 2 | 4) {
+            return None;
+        };
+        Some(with_channels!(self, img => img.alpha()))
+    }
+    /// Opacity of the pixel at column `x` and row `y`, see [`ImageKind::alpha`].
+    pub fn pixel_alpha(&self, x: usize, y: usize) -> Option<f32> {
+        if !matches!(self.num_channels(), 2 | 4) {
+            return None;
+        };
+        with_channels!(self, img => img.pixel_alpha(x, y))
+    }
+
+    /// Range of values a threshold can be chosen from.
+    pub fn value_range(&self) -> (f32, f32) {
+        match self {
+            ImageKind::U8(_) => DType::U8.range(),
+            _ => with_channels!(self, img => data_range(img.as_slice())),
+        }
+    }
+
+    /// Pixel values as shown in the tooltip, e.g. `[12, 34, 56]`.
+    pub fn pixel_label(&self, x: usize, y: usize) -> Option<String> {
+        let dtype = self.dtype();
+        let values: Vec<String> = with_channels!(self, img => img
+            .pixel(x, y)?
+            .iter()
+            .map(|v| dtype.format_value(v.to_f32()))
+            .collect());
+        Some(format!("[{}]", values.join(", ")))
+    }
+
+    /// See [`ChannelsKind::sample`].
+    pub fn sample(&self, region: &Region) -> Result<ImageKind, ImageError> {
+        Ok(match self {
+            ImageKind::U8(img) => ImageKind::U8(img.sample(region)?),
+            ImageKind::U16(img) => ImageKind::U16(img.sample(region)?),
+            ImageKind::F32(img, dtype) => ImageKind::F32(img.sample(region)?, *dtype),
+        })
+    }
+
+    pub fn channel(&self, channel_i: usize) -> Result<ImageKind, ImageError> {
+        Ok(match self {
+            ImageKind::U8(img) => ImageKind::U8(ChannelsKind::One(img.channel(channel_i)?)),
+            ImageKind::U16(img) => ImageKind::U16(ChannelsKind::One(img.channel(channel_i)?)),
+            ImageKind::F32(img, dtype) => {
+                ImageKind::F32(ChannelsKind::One(img.channel(channel_i)?), *dtype)
+            }
+        })
+    }
+
+    pub fn change_color(&self, color: ColorSpaceChange) -> Result<ImageKind, ImageError> {
+        Ok(match self {
+            ImageKind::U8(img) => ImageKind::U8(img.change_color(color)?),
+            ImageKind::U16(img) => ImageKind::U16(img.change_color(color)?),
+            ImageKind::F32(img, dtype) => {
+                // computed values are no longer of the source integer type
+                let dtype = if color.is_reordering() {
+                    *dtype
+                } else {
+                    DType::F32
+                };
+                ImageKind::F32(img.change_color(color)?, dtype)
+            }
+        })
+    }
+}
+
+/// An image with the title of its panel.
+#[derive(Clone)]
+pub struct NamedImage {
+    pub title: String,
+    pub image: ImageKind,
+    /// Every slice of a multi-page TIFF or a volume, `image` is the first one.
+    pub stack: Option<Vec<ImageKind>>,
+    /// Source frames of YUV images, one per slice.
+    pub yuv: Option<Vec<YuvFrame>>,
+    /// The last channel is opacity, see [`ImageKind::alpha`].
+    pub alpha: bool,
+    /// File the image was read from, `None` for arrays sent from Python.
+    pub path: Option<PathBuf>,
+    /// Array that `image` is a preview of, read as it is shown.
+    pub paged: Option<Arc<PagedNpy>>,
+    /// Decoded from an image file or YUV frames, so already in RGB order.
+    pub decoded: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum ColorSpaceChange {
+    BGR2RGB,
+    RGB2GRAY,
+    RGB2HSV,
+    GRAY2RGB,
+    BGRA2RGBA,
+    RGBA2RGB,
+}
+
+impl ColorSpaceChange {
+    /// Conversions that can be applied to an image with `channels` channels.
+    pub fn available_for(channels: usize) -> &'static [ColorSpaceChange] {
+        match channels {
+            1 => &[ColorSpaceChange::GRAY2RGB],
+            3 => &[
+                ColorSpaceChange::BGR2RGB,
+                ColorSpaceChange::RGB2GRAY,
+                ColorSpaceChange::RGB2HSV,
+            ],
+            4 => &[ColorSpaceChange::BGRA2RGBA, ColorSpaceChange::RGBA2RGB],
+            _ => &[],
+        }
+    }
+    fn source_channels(&self) -> usize {
+        match self {
+            ColorSpaceChange::GRAY2RGB => 1,
+            ColorSpaceChange::BGR2RGB | ColorSpaceChange::RGB2GRAY | ColorSpaceChange::RGB2HSV => 3,
+            ColorSpaceChange::BGRA2RGBA | ColorSpaceChange::RGBA2RGB => 4,
+        }
+    }
+    /// Whether the conversion only moves channel values around.
+    fn is_reordering(&self) -> bool {
+        !matches!(self, ColorSpaceChange::RGB2GRAY | ColorSpaceChange::RGB2HSV)
+    }
+}
+
+impl fmt::Display for ColorSpaceChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ColorSpaceChange::BGR2RGB => "BGR => RGB",
+            ColorSpaceChange::RGB2GRAY => "RGB => GRAY",
+            ColorSpaceChange::RGB2HSV => "RGB => HSV",
+            ColorSpaceChange::GRAY2RGB => "GRAY => RGB",
+            ColorSpaceChange::BGRA2RGBA => "BGRA => RGBA",
+            ColorSpaceChange::RGBA2RGB => "RGBA => RGB",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_takes_every_step_th_pixel_of_the_region() {
+        let size = ImageSize {
+            width: 5,
+            height: 4,
+        };
+        let data: Vec<u16> = (0..40).collect();
+        let image = ImageKind::U16(ChannelsKind::new(size, 2, data).unwrap());
+        let region = Region {
+            x: 1,
+            y: 1,
+            width: 4,
+            height: 3,
+            step: 2,
+        };
+        let ImageKind::U16(sampled) = image.sample(&region).unwrap() else {
+            panic!("the pixel type is kept");
+        };
+        assert_eq!((sampled.size().width, sampled.size().height), (2, 2));
+        assert_eq!(sampled.as_slice(), [12, 13, 16, 17, 32, 33, 36, 37]);
+
+        let whole = Region {
+            x: 0,
+            y: 0,
+            width: 5,
+            height: 4,
+            step: 1,
+        };
+        assert_eq!(
+            image.sample(&whole).unwrap().pixel_label(4, 3),
+            image.pixel_label(4, 3)
+        );
+    }
+
+    #[test]
+    fn sample_keeps_the_source_dtype() {
+        let size = ImageSize {
+            width: 3,
+            height: 1,
+        };
+        let image = ImageKind::F32(
+            ChannelsKind::new(size, 1, vec![1., 2., 3.]).unwrap(),
+            DType::I32,
+        );
+        let region = Region {
+            x: 2,
+            y: 0,
+            width: 1,
+            height: 1,
+            step: 4,
+        };
+        let sampled = image.sample(&region).unwrap();
+        assert_eq!(sampled.dtype(), DType::I32);
+        assert_eq!(sampled.pixel_label(0, 0).unwrap(), "[3]");
+    }
+}
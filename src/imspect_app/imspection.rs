@@ -1,195 +1,391 @@
 use std::cmp::PartialEq;
 use std::fmt;
+use std::path::PathBuf;
 
-use crate::imspect_app::textures::apply_threshold;
+use crate::imspect_app::bayer::{self, CfaPattern, Demosaic};
+use crate::imspect_app::image_kind::{ColorSpaceChange, ImageKind, NamedImage};
+use crate::imspect_app::paged_view::PagedView;
+use crate::imspect_app::stack::Stack;
+use crate::imspect_app::textures::{apply_threshold, display_range};
+use crate::imspect_app::tiles::TiledTexture;
+use crate::ipc::Param;
+use crate::yuv::{YuvFrame, YuvPlane};
 use eframe::epaint::TextureHandle;
-use kornia::image::{Image, ImageError, ImageSize};
-use kornia::imgproc::color;
+use kornia::image::ImageError;
 
-#[derive(Clone)]
-pub enum ImageKind {
-    OneChannel(Image<u8, 1>),
-    ThreeChannel(Image<u8, 3>),
+/// How values of a non-`uint8` image are mapped to 8 bits for display.
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub enum DisplayRange {
+    /// Stretch the actual min..max of the image.
+    #[default]
+    Auto,
+    /// Use the full range of the source dtype.
+    Dtype,
+    /// Stretch a chosen window of values, the ones outside are clipped.
+    Window { min: f32, max: f32 },
 }
 
-impl ImageKind {
-    pub fn num_channels(&self) -> usize {
-        match self {
-            ImageKind::OneChannel(_) => 1,
-            ImageKind::ThreeChannel(_) => 3,
-        }
-    }
-    pub fn width(&self) -> usize {
+impl fmt::Display for DisplayRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ImageKind::OneChannel(img) => img.width(),
-            ImageKind::ThreeChannel(img) => img.width(),
+            DisplayRange::Auto => write!(f, "Auto"),
+            DisplayRange::Dtype => write!(f, "Dtype"),
+            DisplayRange::Window { .. } => write!(f, "Window"),
         }
     }
-    pub fn height(&self) -> usize {
-        match self {
-            ImageKind::OneChannel(img) => img.height(),
-            ImageKind::ThreeChannel(img) => img.height(),
-        }
+}
+
+/// How the color of gray+alpha and RGBA images is shown.
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
+pub enum AlphaView {
+    /// Blended over the background by opacity.
+    #[default]
+    Composite,
+    /// The color channels as stored, opacity ignored.
+    Straight,
+    /// The color multiplied by opacity, transparent regions black.
+    Premultiplied,
+}
+
+impl fmt::Display for AlphaView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
 pub struct SingleImspection {
+    pub title: String,
+    /// The image shown, the current slice for stacks.
     pub image: ImageKind,
+    pub stack: Option<Stack>,
+    /// Source frames of YUV images, one per slice, see [`Operation::Plane`].
+    pub yuv: Option<Vec<YuvFrame>>,
+    /// The last channel is opacity, see [`ImageKind::alpha`].
+    pub alpha: bool,
+    /// Array read in place, from disk, that `image` is an overview of.
+    pub paged: Option<PagedView>,
+    pub alpha_view: AlphaView,
+    /// The whole image, or the coarsest level of `tiles` for images too large for one texture.
     pub texture: Option<TextureHandle>,
+    pub tiles: Option<TiledTexture>,
     pub id: usize,
     pub need_rerender: bool,
     pub remove_flag: bool,
     pub thr: ThrSettings,
+    pub display: DisplayRange,
+    /// Range of the threshold slider.
+    pub value_range: (f32, f32),
+    /// `Auto` range of stacks over every slice, for the threshold it was computed with.
+    pub stack_range: Option<(ThrSettings, (f32, f32))>,
+    /// How the panel was made from another one, `None` if it was passed in.
+    pub derived: Option<Derivation>,
+    /// Rendered as a full panel, otherwise only in the thumbnail strip.
+    pub shown: bool,
+    /// File the image was read from, reloaded with `--watch`.
+    pub path: Option<PathBuf>,
 }
 
-impl SingleImspection {
-    pub fn apply_threshold(&self) -> Option<ImageKind> {
-        apply_threshold(&self.image, &self.thr).map(ImageKind::OneChannel)
+/// An operation that made a derived panel, redone when its parent changes.
+#[derive(Clone, Copy)]
+pub enum Operation {
+    /// A clone, thresholded unless the kind is `None`.
+    Threshold(ThrSettings),
+    Channel(usize),
+    Color(ColorSpaceChange),
+    /// A plane of the YUV frame the image was converted from.
+    Plane(YuvPlane),
+    Demosaic(CfaPattern, Demosaic),
+    ColorizeCfa(CfaPattern),
+}
+
+impl Operation {
+    /// `yuv` is the frame `image` was converted from, if any.
+    pub fn apply(
+        &self,
+        image: &ImageKind,
+        yuv: Option<&YuvFrame>,
+    ) -> Result<ImageKind, ImageError> {
+        match self {
+            Operation::Threshold(thr) => {
+                Ok(apply_threshold(image, thr).unwrap_or_else(|| image.clone()))
+            }
+            Operation::Channel(i) => image.channel(*i),
+            Operation::Color(color) => image.change_color(*color),
+            Operation::Demosaic(pattern, method) => bayer::demosaic(image, *pattern, *method),
+            Operation::ColorizeCfa(pattern) => bayer::colorize_cfa(image, *pattern),
+            Operation::Plane(plane) => match yuv {
+                Some(frame) => frame.plane(*plane),
+                // planes are only offered for panels with YUV frames
+                None => Err(ImageError::InvalidChannelShape(image.num_channels(), 3)),
+            },
+        }
     }
-    pub fn clone_with_thr(&self, id: usize) -> Self {
-        let new_img = if let Some(new_image) = self.apply_threshold() {
-            new_image
-        } else {
-            self.image.to_owned()
-        };
+
+    /// Parameters naming the operation and its arguments.
+    pub fn params(&self) -> Vec<(&'static str, Param)> {
+        let name = |name: &str| ("operation", Param::Text(name.to_owned()));
+        match self {
+            Operation::Threshold(thr) => vec![
+                name("threshold"),
+                ("operation_threshold", Param::Text(thr.kind.to_string())),
+                ("operation_threshold_value", Param::Float(thr.value)),
+            ],
+            Operation::Channel(i) => vec![name("channel"), ("channel", Param::Int(*i as i64))],
+            Operation::Color(color) => {
+                vec![name("color"), ("color", Param::Text(color.to_string()))]
+            }
+            Operation::Plane(plane) => {
+                vec![name("plane"), ("plane", Param::Text(plane.to_string()))]
+            }
+            Operation::Demosaic(pattern, method) => vec![
+                name("demosaic"),
+                ("cfa_pattern", Param::Text(pattern.to_string())),
+                ("demosaic", Param::Text(method.to_string())),
+            ],
+            Operation::ColorizeCfa(pattern) => vec![
+                name("colorize_cfa"),
+                ("cfa_pattern", Param::Text(pattern.to_string())),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Derivation {
+    pub parent_id: usize,
+    pub operation: Operation,
+}
+
+impl SingleImspection {
+    pub fn new(title: String, image: ImageKind, id: usize) -> Self {
+        let value_range = image.value_range();
+        let alpha = image.num_channels() == 4;
         Self {
-            image: new_img,
+            title,
+            image,
+            stack: None,
+            yuv: None,
+            alpha,
+            paged: None,
+            alpha_view: Default::default(),
             texture: None,
+            tiles: None,
             id,
             need_rerender: true,
             remove_flag: false,
             thr: Default::default(),
+            display: Default::default(),
+            value_range,
+            stack_range: None,
+            derived: None,
+            shown: true,
+            path: None,
         }
     }
-
-    pub fn new_with_took_channel(
-        image: &ImageKind,
-        channel_i: usize,
+    /// A panel of a loaded image, stacks start at their first slice.
+    pub fn from_named(img: NamedImage, id: usize) -> Self {
+        Self {
+            path: img.path,
+            yuv: img.yuv,
+            alpha: img.alpha,
+            paged: img.paged.map(PagedView::new),
+            ..Self::new(img.title, img.image, id).with_stack(img.stack.map(Stack::new))
+        }
+    }
+    fn with_stack(mut self, stack: Option<Stack>) -> Self {
+        if let Some(stack) = &stack {
+            self.image = stack.current().clone();
+            self.value_range = stack.value_range();
+        };
+        self.stack = stack;
+        self
+    }
+    /// With [`Stack::whole_stack`] the operation is applied to every slice.
+    fn new_derived(
+        &self,
+        operation: Operation,
+        suffix: &str,
         id: usize,
     ) -> Result<Self, ImageError> {
-        let new_img = match &image {
-            ImageKind::OneChannel(img) => img.channel(channel_i)?,
-            ImageKind::ThreeChannel(img) => img.channel(channel_i)?,
+        let stack = match &self.stack {
+            Some(stack) if stack.whole_stack => {
+                Some(stack.map(|i, slice| operation.apply(slice, self.yuv_frame(i)))?)
+            }
+            _ => None,
         };
-        Ok(SingleImspection {
-            image: ImageKind::OneChannel(new_img),
-            texture: None,
-            id,
-            need_rerender: true,
-            remove_flag: false,
-            thr: Default::default(),
+        let image = match &stack {
+            Some(stack) => stack.current().clone(),
+            None => operation.apply(&self.image, self.yuv_frame(self.current_slice()))?,
+        };
+        // gray+alpha stays so through clones, four channels are always RGBA
+        let alpha = match image.num_channels() {
+            2 => self.alpha && self.image.num_channels() == 2,
+            channels => channels == 4,
+        };
+        Ok(Self {
+            alpha,
+            derived: Some(Derivation {
+                parent_id: self.id,
+                operation,
+            }),
+            ..Self::new(self.derived_title(suffix), image, id).with_stack(stack)
         })
     }
+    /// Redoes the operation that made this panel from a new version of its parent.
+    pub fn rederive(&mut self, parent: &SingleImspection) -> Result<(), ImageError> {
+        let Some(derivation) = self.derived else {
+            return Ok(());
+        };
+        match (&parent.stack, &self.stack) {
+            (Some(parent_stack), Some(_)) => {
+                let stack = parent_stack
+                    .map(|i, slice| derivation.operation.apply(slice, parent.yuv_frame(i)))?;
+                self.replace_image(stack.current().clone(), Some(stack.into_slices()));
+            }
+            _ => {
+                let frame = parent.yuv_frame(parent.current_slice());
+                self.replace_image(derivation.operation.apply(&parent.image, frame)?, None)
+            }
+        };
+        Ok(())
+    }
+    /// Swaps in a new version of the image or of the stacked slices,
+    /// keeping the settings of the panel.
+    pub fn replace_image(&mut self, image: ImageKind, slices: Option<Vec<ImageKind>>) {
+        match (&mut self.stack, slices) {
+            (Some(stack), Some(slices)) => stack.replace_slices(slices),
+            (None, Some(slices)) => self.stack = Some(Stack::new(slices)),
+            (_, None) => self.stack = None,
+        };
+        self.stack_range = None;
+        match &self.stack {
+            Some(stack) => {
+                self.value_range = stack.value_range();
+                self.image = stack.current().clone();
+            }
+            None => {
+                self.value_range = image.value_range();
+                self.image = image;
+            }
+        };
+        self.need_rerender = true;
+    }
+    /// Index of the slice shown, 0 for single images.
+    fn current_slice(&self) -> usize {
+        self.stack.as_ref().map_or(0, |stack| stack.current)
+    }
+    fn yuv_frame(&self, slice: usize) -> Option<&YuvFrame> {
+        self.yuv.as_ref()?.get(slice)
+    }
+    /// Shows the current slice of the stack after it moved.
+    pub fn show_current_slice(&mut self) {
+        if let Some(stack) = &self.stack {
+            self.image = stack.current().clone();
+            self.need_rerender = true;
+        };
+    }
+    pub fn apply_threshold(&self) -> Option<ImageKind> {
+        apply_threshold(&self.image, &self.thr)
+    }
+    /// Computes the `Auto` range of a stack for the current threshold, if it changed.
+    /// Every slice shares it, so the brightness holds still while playing.
+    pub fn update_stack_range(&mut self) {
+        let Some(stack) = &self.stack else {
+            return;
+        };
+        if self.stack_range.is_some_and(|(thr, _)| thr == self.thr) {
+            return;
+        };
+        let thr = self.thr;
+        let range = stack.range_by(|slice| match apply_threshold(slice, &thr) {
+            Some(thr_slice) => thr_slice.value_range(),
+            None => slice.value_range(),
+        });
+        self.stack_range = Some((thr, range));
+    }
+    /// Range of values stretched to 8 bits, `range_of` being the image whose values
+    /// are stretched, see [`DisplayRange`]. Stacks use the range of every slice in `Auto`.
+    pub fn display_range(&self, range_of: &ImageKind) -> (f32, f32) {
+        match (self.display, self.stack_range) {
+            (DisplayRange::Auto, Some((thr, range))) if thr == self.thr => range,
+            (display, _) => display_range(range_of, display),
+        }
+    }
+    pub fn clone_with_thr(&self, id: usize) -> Result<Self, ImageError> {
+        let suffix = if self.thr.kind == Threshold::None {
+            "clone".to_string()
+        } else {
+            let value = self.image.dtype().format_value(self.thr.value);
+            format!("{}>{}", self.thr.kind, value)
+        };
+        Ok(Self {
+            display: self.display,
+            alpha_view: self.alpha_view,
+            ..self.new_derived(Operation::Threshold(self.thr), &suffix, id)?
+        })
+    }
+
+    pub fn new_with_took_channel(&self, channel_i: usize, id: usize) -> Result<Self, ImageError> {
+        self.new_derived(
+            Operation::Channel(channel_i),
+            &format!("ch {}", channel_i + 1),
+            id,
+        )
+    }
+    /// Opacity of a gray+alpha or RGBA image as a panel of its own.
+    pub fn new_with_alpha(&self, id: usize) -> Result<Self, ImageError> {
+        let last = self.image.num_channels() - 1;
+        self.new_derived(Operation::Channel(last), "alpha", id)
+    }
+    pub fn new_with_yuv_plane(&self, plane: YuvPlane, id: usize) -> Result<Self, ImageError> {
+        self.new_derived(Operation::Plane(plane), &format!("{} plane", plane), id)
+    }
+    pub fn new_with_demosaic(
+        &self,
+        pattern: CfaPattern,
+        method: Demosaic,
+        id: usize,
+    ) -> Result<Self, ImageError> {
+        self.new_derived(
+            Operation::Demosaic(pattern, method),
+            &format!("{} {}", pattern, method),
+            id,
+        )
+    }
+    pub fn new_with_colorized_cfa(
+        &self,
+        pattern: CfaPattern,
+        id: usize,
+    ) -> Result<Self, ImageError> {
+        self.new_derived(
+            Operation::ColorizeCfa(pattern),
+            &format!("{} CFA", pattern),
+            id,
+        )
+    }
     pub fn new_with_changed_color(
-        image: &ImageKind,
+        &self,
         color: ColorSpaceChange,
         id: usize,
     ) -> Result<Self, ImageError> {
-        match image {
-            ImageKind::OneChannel(img) => {
-                if matches!(color, ColorSpaceChange::GRAY2RGB) {
-                    let mut new_img = Image::<f32, 3>::from_size_val(
-                        ImageSize {
-                            width: img.width(),
-                            height: img.height(),
-                        },
-                        0.,
-                    )?;
-                    color::rgb_from_gray(&img.cast::<f32>().unwrap(), &mut new_img)?;
-                    let new_img = new_img.cast::<u8>()?;
-                    Ok(SingleImspection {
-                        image: ImageKind::ThreeChannel(new_img),
-                        texture: None,
-                        id,
-                        need_rerender: true,
-                        remove_flag: false,
-                        thr: Default::default(),
-                    })
-                } else {
-                    Err(ImageError::InvalidChannelShape(3, 1))
-                }
-            }
-            ImageKind::ThreeChannel(img) => match color {
-                ColorSpaceChange::GRAY2RGB => Err(ImageError::InvalidChannelShape(1, 3)),
-                ColorSpaceChange::BGR2RGB => {
-                    let mut new_img = Image::<u8, 3>::from_size_val(
-                        ImageSize {
-                            width: img.width(),
-                            height: img.height(),
-                        },
-                        0,
-                    )
-                    .unwrap();
-                    color::bgr_from_rgb(img, &mut new_img).unwrap();
-                    Ok(SingleImspection {
-                        image: ImageKind::ThreeChannel(new_img),
-                        texture: None,
-                        id,
-                        need_rerender: true,
-                        remove_flag: false,
-                        thr: Default::default(),
-                    })
-                }
-                ColorSpaceChange::RGB2GRAY => {
-                    let mut new_img = Image::<f32, 1>::from_size_val(
-                        ImageSize {
-                            width: img.width(),
-                            height: img.height(),
-                        },
-                        0.,
-                    )
-                    .unwrap();
-                    color::gray_from_rgb(&img.cast::<f32>().unwrap(), &mut new_img).unwrap();
-                    let new_img = new_img.cast::<u8>().unwrap();
-                    Ok(SingleImspection {
-                        image: ImageKind::OneChannel(new_img),
-                        texture: None,
-                        id,
-                        need_rerender: true,
-                        remove_flag: false,
-                        thr: Default::default(),
-                    })
-                }
-                ColorSpaceChange::RGB2HSV => {
-                    let mut new_img = Image::<f32, 3>::from_size_val(
-                        ImageSize {
-                            width: img.width(),
-                            height: img.height(),
-                        },
-                        0.,
-                    )
-                    .unwrap();
-
-                    color::hsv_from_rgb(&img.cast::<f32>().unwrap(), &mut new_img).unwrap();
-                    let new_img = new_img.cast::<u8>().unwrap();
-                    Ok(SingleImspection {
-                        image: ImageKind::ThreeChannel(new_img),
-                        texture: None,
-                        id,
-                        need_rerender: true,
-                        remove_flag: false,
-                        thr: Default::default(),
-                    })
-                }
-            },
-        }
+        self.new_derived(Operation::Color(color), &color.to_string(), id)
+    }
+
+    /// Title of a panel made from this one, e.g. `mask | ch 2`.
+    fn derived_title(&self, operation: &str) -> String {
+        format!("{} | {}", self.title, operation)
     }
-}
-pub enum ColorSpaceChange {
-    BGR2RGB,
-    RGB2GRAY,
-    RGB2HSV,
-    GRAY2RGB,
 }
 
-#[derive(Default)]
+#[derive(PartialEq, Default, Clone, Copy)]
 pub struct ThrSettings {
     pub kind: Threshold,
-    pub value: u8,
+    pub value: f32,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Default, Clone, Copy)]
 pub enum Threshold {
+    #[default]
     None,
     Binary,
     BinaryInv,
@@ -204,9 +400,3 @@ impl fmt::Display for Threshold {
         write!(f, "{:?}", self)
     }
 }
-
-impl Default for Threshold {
-    fn default() -> Self {
-        Self::None
-    }
-}
@@ -1,4 +1,12 @@
 pub mod app;
+pub mod bayer;
+pub mod gallery;
+pub mod image_kind;
 pub mod imspection;
+pub mod paged_view;
+pub mod raw_dialog;
 pub mod run;
+pub mod stack;
 pub mod textures;
+pub mod tiles;
+pub mod watch;
@@ -0,0 +1,191 @@
+//! Panels of arrays opened in place: an overview of the whole array sampled in the background,
+//! and the visible part read in the background at the resolution it is shown at once zoomed
+//! in past it.
+
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use egui::TextureHandle;
+
+use crate::imspect_app::image_kind::ImageKind;
+use crate::paged::{PagedNpy, Region, OVERVIEW_SIDE, PREVIEW_SIDE};
+
+enum Overview {
+    Pending,
+    Sampling(Receiver<Result<ImageKind, String>>),
+    Done,
+}
+
+/// Reading of the part of the array around the visible one.
+enum DetailRead {
+    Idle,
+    Reading(Region, Receiver<Result<ImageKind, String>>),
+    /// The region couldn't be read, it isn't tried again until the view moves off it.
+    Failed(Region),
+}
+
+/// Whether `read` shows `visible` at the resolution it is shown at.
+fn covers(read: Region, visible: Region) -> bool {
+    read.step == visible.step && read.contains(&visible)
+}
+
+pub struct PagedView {
+    pub file: Arc<PagedNpy>,
+    /// Array pixels per pixel of the image of the panel, along both axes.
+    pub step: usize,
+    overview: Overview,
+    /// Part of the array sampled finer than the overview, with its texture.
+    pub detail: Option<(Region, TextureHandle)>,
+    detail_read: DetailRead,
+}
+
+impl PagedView {
+    /// The view of an array opened with its preview.
+    pub fn new(file: Arc<PagedNpy>) -> Self {
+        let step = file.step_for(PREVIEW_SIDE);
+        let overview = if file.step_for(OVERVIEW_SIDE) < step {
+            Overview::Pending
+        } else {
+            Overview::Done
+        };
+        Self {
+            file,
+            step,
+            overview,
+            detail: None,
+            detail_read: DetailRead::Idle,
+        }
+    }
+
+    /// Starts sampling the overview in a background thread, returns it once it is done.
+    pub fn poll_overview(&mut self, ctx: &egui::Context) -> Option<Result<ImageKind, String>> {
+        match &self.overview {
+            Overview::Pending => {
+                let (sender, receiver) = mpsc::channel();
+                let file = self.file.clone();
+                let ctx = ctx.clone();
+                thread::spawn(move || {
+                    let overview = file
+                        .overview(OVERVIEW_SIDE)
+                        .map_err(|err| format!("{:#}", err));
+                    // the window may be closed before the overview is done
+                    let _ = sender.send(overview);
+                    ctx.request_repaint();
+                });
+                self.overview = Overview::Sampling(receiver);
+                None
+            }
+            Overview::Sampling(receiver) => match receiver.try_recv() {
+                Ok(overview) => {
+                    self.overview = Overview::Done;
+                    if overview.is_ok() {
+                        self.step = self.file.step_for(OVERVIEW_SIDE);
+                    };
+                    Some(overview)
+                }
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => {
+                    self.overview = Overview::Done;
+                    None
+                }
+            },
+            Overview::Done => None,
+        }
+    }
+
+    /// The visible part of the array, `[x0, y0, x1, y1]` in array pixels, with the step
+    /// that samples it to `screen_width` pixels. `None` while the overview is as fine.
+    pub fn visible_region(&self, visible: [f64; 4], screen_width: f32) -> Option<Region> {
+        let [x0, y0, x1, y1] = visible;
+        let clamp = |value: f64, max: usize| (value.max(0.) as usize).min(max);
+        let (x0, x1) = (
+            clamp(x0.floor(), self.file.width),
+            clamp(x1.ceil(), self.file.width),
+        );
+        let (y0, y1) = (
+            clamp(y0.floor(), self.file.height),
+            clamp(y1.ceil(), self.file.height),
+        );
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        };
+        let step = ((x1 - x0) as f32 / screen_width.max(1.)).max(1.) as usize;
+        // powers of two, so that small zooms keep the detail already read
+        let step = 1 << step.ilog2();
+        if step >= self.step {
+            return None;
+        };
+        Some(Region {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+            step,
+        })
+    }
+
+    /// Drops the detail and any read of it in progress.
+    pub fn clear_detail(&mut self) {
+        self.detail = None;
+        self.detail_read = DetailRead::Idle;
+    }
+
+    /// Starts reading the part around `visible` in a background thread, unless the detail
+    /// or a read in progress covers it already. Returns the part once it is read.
+    pub fn poll_detail(
+        &mut self,
+        ctx: &egui::Context,
+        visible: Region,
+    ) -> Option<(Region, ImageKind)> {
+        match &self.detail_read {
+            DetailRead::Reading(region, receiver) => match receiver.try_recv() {
+                Ok(Ok(detail)) => {
+                    let region = *region;
+                    self.detail_read = DetailRead::Idle;
+                    return Some((region, detail));
+                }
+                Ok(Err(_)) => {
+                    self.detail_read = DetailRead::Failed(*region);
+                    return None;
+                }
+                Err(TryRecvError::Empty) if covers(*region, visible) => return None,
+                // the view moved off it, the result is dropped
+                Err(_) => self.detail_read = DetailRead::Idle,
+            },
+            DetailRead::Failed(region) if covers(*region, visible) => return None,
+            DetailRead::Failed(_) | DetailRead::Idle => {}
+        };
+        if let Some((read, _)) = &self.detail {
+            if covers(*read, visible) {
+                return None;
+            };
+        };
+
+        let region = self.with_margin(visible);
+        let (sender, receiver) = mpsc::channel();
+        let file = self.file.clone();
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let detail = file.region(region).map_err(|err| format!("{:#}", err));
+            // the read may be superseded before it's done
+            let _ = sender.send(detail);
+            ctx.request_repaint();
+        });
+        self.detail_read = DetailRead::Reading(region, receiver);
+        None
+    }
+
+    /// `visible` grown by half its size on every side, so that panning keeps it for a while.
+    fn with_margin(&self, visible: Region) -> Region {
+        let x = visible.x.saturating_sub(visible.width / 2);
+        let y = visible.y.saturating_sub(visible.height / 2);
+        Region {
+            x,
+            y,
+            width: (visible.x + visible.width + visible.width / 2).min(self.file.width) - x,
+            height: (visible.y + visible.height + visible.height / 2).min(self.file.height) - y,
+            step: visible.step,
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Window that opens a headerless file with a layout chosen in the GUI.
+
+use std::path::Path;
+
+use egui::{ComboBox, DragValue, Grid};
+
+use crate::imspect_app::image_kind::NamedImage;
+use crate::input::load_image;
+use crate::raw::{self, Endian, RawSpec, RAW_DTYPES};
+use crate::yuv::{YuvFormat, YUV_FORMATS};
+
+#[derive(Default)]
+pub struct RawDialog {
+    path: String,
+    spec: RawSpec,
+    /// Read YUV frames of the spec size instead of samples.
+    yuv: Option<YuvFormat>,
+    error: Option<String>,
+}
+
+impl RawDialog {
+    /// Shows the window while `open`, returns the image once it loads.
+    pub fn show(&mut self, ctx: &egui::Context, open: &mut bool) -> Option<NamedImage> {
+        let mut loaded = None;
+        let mut close = false;
+        egui::Window::new("Open raw buffer")
+            .open(open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                Grid::new("Raw buffer layout")
+                    .num_columns(2)
+                    .show(ui, |ui| self.render_fields(ui));
+
+                ui.horizontal(|ui| {
+                    let spec = self.spec_arg();
+                    if ui.button("Open").clicked() {
+                        let arg = raw::join_arg(Path::new(self.path.trim()), &spec);
+                        match load_image(&arg) {
+                            Ok(img) => {
+                                self.error = None;
+                                loaded = Some(img);
+                                close = true;
+                            }
+                            Err(err) => self.error = Some(format!("{:#}", err)),
+                        };
+                    };
+                    ui.weak(format!(":{}", spec));
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                };
+            });
+        if close {
+            *open = false;
+        };
+        loaded
+    }
+
+    /// The spec written after the path on the command line.
+    fn spec_arg(&self) -> String {
+        match self.yuv {
+            Some(format) => format!("{}x{}:{}", self.spec.width, self.spec.height, format),
+            None => self.spec.to_string(),
+        }
+    }
+
+    fn render_fields(&mut self, ui: &mut egui::Ui) {
+        let spec = &mut self.spec;
+
+        ui.label("File");
+        ui.text_edit_singleline(&mut self.path);
+        ui.end_row();
+
+        ui.label("Size");
+        ui.horizontal(|ui| {
+            ui.add(DragValue::new(&mut spec.width).range(1..=usize::MAX));
+            ui.label("x");
+            ui.add(DragValue::new(&mut spec.height).range(1..=usize::MAX));
+        });
+        ui.end_row();
+
+        ui.label("Dtype");
+        ui.horizontal(|ui| {
+            let selected = match self.yuv {
+                Some(format) => format.to_string(),
+                None => spec.dtype.to_string(),
+            };
+            ComboBox::from_id_salt("Raw dtype")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for dtype in RAW_DTYPES {
+                        let chosen = self.yuv.is_none() && spec.dtype == *dtype;
+                        if ui.selectable_label(chosen, dtype.to_string()).clicked() {
+                            spec.dtype = *dtype;
+                            self.yuv = None;
+                        };
+                    }
+                    ui.separator();
+                    for format in YUV_FORMATS {
+                        ui.selectable_value(&mut self.yuv, Some(*format), format.to_string());
+                    }
+                });
+            if self.yuv.is_some() {
+                return;
+            };
+            if raw::sample_size(spec.dtype).unwrap_or(1) > 1 {
+                ui.radio_value(&mut spec.endian, Endian::Little, "little-endian");
+                ui.radio_value(&mut spec.endian, Endian::Big, "big-endian");
+            };
+        });
+        ui.end_row();
+        if self.yuv.is_some() {
+            return;
+        };
+
+        ui.label("Channels");
+        ui.add(DragValue::new(&mut spec.channels).range(1..=4));
+        ui.end_row();
+
+        ui.label("Row stride");
+        ui.horizontal(|ui| {
+            let mut custom = spec.stride.is_some();
+            ui.checkbox(&mut custom, "");
+            match (custom, &mut spec.stride) {
+                (true, Some(stride)) => {
+                    ui.add(DragValue::new(stride).suffix(" bytes"));
+                }
+                (true, None) => {
+                    spec.stride = spec.row_len();
+                }
+                (false, _) => {
+                    spec.stride = None;
+                    ui.weak("packed rows");
+                }
+            };
+        });
+        ui.end_row();
+
+        ui.label("Offset");
+        ui.add(DragValue::new(&mut spec.offset).suffix(" bytes"));
+        ui.end_row();
+    }
+}
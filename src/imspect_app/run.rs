@@ -1,14 +1,79 @@
+use std::path::PathBuf;
+
+use eframe::egui::Vec2;
+
 use crate::imspect_app::app::ImspectApp;
-use crate::imspect_app::imspection::ImageKind;
+use crate::imspect_app::image_kind::NamedImage;
+use crate::ipc;
 
-pub fn imspect_kornia_images(imgs: Vec<ImageKind>) -> eframe::Result {
+/// How image panels are arranged in the window.
+#[derive(Default, Clone, Copy, PartialEq, Debug, clap::ValueEnum)]
+pub enum PanelLayout {
+    Grid,
+    #[default]
+    Row,
+    Column,
+}
+
+impl PanelLayout {
+    /// Number of columns and rows taken by `count` panels.
+    pub fn shape(&self, count: usize) -> (usize, usize) {
+        let count = count.max(1);
+        match self {
+            PanelLayout::Row => (count, 1),
+            PanelLayout::Column => (1, count),
+            PanelLayout::Grid => {
+                let columns = (count as f32).sqrt().ceil() as usize;
+                (columns, count.div_ceil(columns))
+            }
+        }
+    }
+}
+
+/// How the viewer is started.
+#[derive(Default)]
+pub struct ViewerOptions {
+    /// Also show images sent by later `imspect` calls from Python.
+    pub listen: bool,
+    /// Send the images back over stdout when the window is closed.
+    pub report_on_exit: bool,
+    /// Files that failed to load, listed in a panel of their own.
+    pub load_errors: Vec<String>,
+    /// Window title, `imspect` if not set.
+    pub title: Option<String>,
+    pub panel_layout: PanelLayout,
+    /// Inner size of the window in points.
+    pub window_size: Option<Vec2>,
+    /// UI scale, the scale of the display if not set.
+    pub pixels_per_point: Option<f32>,
+    /// Files decoded on demand from a gallery rather than opened as panels.
+    pub gallery: Option<Vec<PathBuf>>,
+    /// Reload the files of the images when they change.
+    pub watch: bool,
+    /// Swap the channels of BGR(A) files that the viewer reads itself.
+    pub bgr: bool,
+}
+
+pub fn imspect_kornia_images(imgs: Vec<NamedImage>, options: ViewerOptions) -> eframe::Result {
+    let mut viewport =
+        egui::ViewportBuilder::default().with_title(options.title.as_deref().unwrap_or("imspect"));
+    if let Some(size) = options.window_size {
+        viewport = viewport.with_inner_size(size);
+    };
     let native_options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default(),
+        viewport,
         ..Default::default()
     };
     eframe::run_native(
         "imspect",
         native_options,
-        Box::new(|cc| Ok(Box::new(ImspectApp::new(cc, imgs)))),
+        Box::new(move |cc| {
+            let incoming = if options.listen {
+                ipc::listen(cc.egui_ctx.clone())
+            } else {
+                None
+            };
+            Ok(Box::new(ImspectApp::new(cc, imgs, incoming, options)))
+        }),
     )
 }
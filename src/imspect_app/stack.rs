@@ -0,0 +1,117 @@
+//! Slices of multi-page files and volumes, browsed one at a time in a panel.
+
+use crate::imspect_app::image_kind::ImageKind;
+
+/// Slices shown per second while playing.
+pub const DEFAULT_FPS: f32 = 10.;
+
+pub struct Stack {
+    slices: Vec<ImageKind>,
+    /// Index of the slice shown in the panel.
+    pub current: usize,
+    pub playing: bool,
+    /// Start over from the first slice instead of stopping at the last one.
+    pub looping: bool,
+    pub fps: f32,
+    /// Operations make a derived stack from every slice, not a single image from the current one.
+    pub whole_stack: bool,
+    /// Time the playback moved to the current slice.
+    last_step: Option<f64>,
+}
+
+impl Stack {
+    /// `slices` must not be empty.
+    pub fn new(slices: Vec<ImageKind>) -> Self {
+        assert!(!slices.is_empty(), "a stack has at least one slice");
+        Self {
+            slices,
+            current: 0,
+            playing: false,
+            looping: true,
+            fps: DEFAULT_FPS,
+            whole_stack: false,
+            last_step: None,
+        }
+    }
+
+    pub fn slice_count(&self) -> usize {
+        self.slices.len()
+    }
+
+    pub fn current(&self) -> &ImageKind {
+        &self.slices[self.current]
+    }
+
+    pub fn into_slices(self) -> Vec<ImageKind> {
+        self.slices
+    }
+
+    /// Range of values over all slices, so sliders don't jump while scrubbing.
+    pub fn value_range(&self) -> (f32, f32) {
+        self.range_by(ImageKind::value_range)
+    }
+
+    /// Union of the ranges `f` gives for every slice.
+    pub fn range_by(&self, f: impl Fn(&ImageKind) -> (f32, f32)) -> (f32, f32) {
+        self.slices.iter().map(f).fold(
+            (f32::INFINITY, f32::NEG_INFINITY),
+            |(lo, hi), (min, max)| (lo.min(min), hi.max(max)),
+        )
+    }
+
+    /// A stack of the transformed slices, positioned at the same slice.
+    /// `f` gets the index of every slice with it.
+    pub fn map<E>(&self, f: impl Fn(usize, &ImageKind) -> Result<ImageKind, E>) -> Result<Self, E> {
+        let slices = self
+            .slices
+            .iter()
+            .enumerate()
+            .map(|(i, slice)| f(i, slice))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            current: self.current,
+            whole_stack: self.whole_stack,
+            ..Self::new(slices)
+        })
+    }
+
+    /// Swaps in new slices, keeping the position and playback settings where possible.
+    pub fn replace_slices(&mut self, slices: Vec<ImageKind>) {
+        assert!(!slices.is_empty(), "a stack has at least one slice");
+        self.current = self.current.min(slices.len() - 1);
+        self.slices = slices;
+    }
+
+    /// Starts or pauses the playback, starting over if it stopped at the last slice.
+    pub fn toggle_playing(&mut self) {
+        self.playing = !self.playing;
+        self.last_step = None;
+        if self.playing && self.current + 1 == self.slice_count() {
+            self.current = 0;
+        };
+    }
+
+    /// Moves to the next slice once its time has come, returns whether the slice changed.
+    pub fn advance(&mut self, now: f64) -> bool {
+        if !self.playing {
+            return false;
+        };
+        let Some(last_step) = self.last_step else {
+            self.last_step = Some(now);
+            return false;
+        };
+        if now - last_step < 1. / self.fps as f64 {
+            return false;
+        };
+        self.last_step = Some(now);
+        if self.current + 1 < self.slice_count() {
+            self.current += 1;
+        } else if self.looping {
+            self.current = 0;
+        } else {
+            self.playing = false;
+            return false;
+        };
+        true
+    }
+}
@@ -1,14 +1,20 @@
-use crate::imspect_app::imspection::{ImageKind, SingleImspection, ThrSettings, Threshold};
-use eframe::epaint::textures::{TextureFilter, TextureOptions};
-use eframe::epaint::ColorImage;
+use std::borrow::Cow;
+
+use crate::imspect_app::image_kind::{ChannelsKind, ImageKind, Pixel};
+use crate::imspect_app::imspection::{
+    AlphaView, DisplayRange, SingleImspection, ThrSettings, Threshold,
+};
+use crate::imspect_app::tiles::TiledTexture;
+use eframe::epaint::textures::TextureOptions;
+use eframe::epaint::{Color32, ColorImage};
 use kornia::image::{Image, ImageError, ImageSize};
 use kornia::imgproc::threshold::{
     threshold_binary, threshold_binary_inverse, threshold_to_zero, threshold_to_zero_inverse,
     threshold_truncate,
 };
 
-pub fn clone_img_as<T, const SRC_C: usize, const DST_C: usize>(
-    img: &Image<u8, SRC_C>,
+pub fn clone_img_as<S, T, const SRC_C: usize, const DST_C: usize>(
+    img: &Image<S, SRC_C>,
 ) -> Result<Image<T, DST_C>, ImageError>
 where
     T: Clone + Default,
@@ -22,60 +28,304 @@ where
     )
 }
 
-fn apply_threshold_func<F>(img: &Image<u8, 1>, value: u8, threshold_func: F) -> Option<Image<u8, 1>>
+fn apply_threshold_func<T, F>(img: &Image<T, 1>, value: T, threshold_func: F) -> Option<Image<T, 1>>
 where
-    F: Fn(&Image<u8, 1>, &mut Image<u8, 1>, u8) -> Result<(), ImageError>,
+    T: Pixel,
+    F: Fn(&Image<T, 1>, &mut Image<T, 1>, T) -> Result<(), ImageError>,
 {
-    let mut new_img = clone_img_as::<u8, 1, 1>(img).ok()?;
+    let mut new_img = clone_img_as::<T, T, 1, 1>(img).ok()?;
     threshold_func(img, &mut new_img, value).ok()?;
     Some(new_img)
 }
-pub fn apply_threshold(image: &ImageKind, thr: &ThrSettings) -> Option<Image<u8, 1>> {
-    let img = match &image {
-        ImageKind::OneChannel(img) => img,
-        _ => return None,
-    };
+
+fn threshold_image<T: Pixel>(img: &Image<T, 1>, thr: &ThrSettings) -> Option<Image<T, 1>> {
+    let value = T::from_f32(thr.value);
 
     match thr.kind {
         Threshold::None => None,
-        Threshold::Binary => apply_threshold_func(img, thr.value, |src, dst, value| {
-            threshold_binary(src, dst, value, u8::MAX)
+        Threshold::Binary => apply_threshold_func(img, value, |src, dst, value| {
+            threshold_binary(src, dst, value, T::MAX_VALUE)
         }),
-        Threshold::BinaryInv => apply_threshold_func(img, thr.value, |src, dst, value| {
-            threshold_binary_inverse(src, dst, value, u8::MAX)
+        Threshold::BinaryInv => apply_threshold_func(img, value, |src, dst, value| {
+            threshold_binary_inverse(src, dst, value, T::MAX_VALUE)
         }),
-        Threshold::ToZero => apply_threshold_func(img, thr.value, threshold_to_zero),
-        Threshold::ToZeroInv => apply_threshold_func(img, thr.value, threshold_to_zero_inverse),
-        Threshold::Truncate => apply_threshold_func(img, thr.value, threshold_truncate),
+        Threshold::ToZero => apply_threshold_func(img, value, threshold_to_zero),
+        Threshold::ToZeroInv => apply_threshold_func(img, value, threshold_to_zero_inverse),
+        Threshold::Truncate => apply_threshold_func(img, value, threshold_truncate),
     }
 }
 
-pub fn prepare_texture(ctx: &egui::Context, imspection: &mut SingleImspection) {
-    if imspection.need_rerender {
-        let color_img: ColorImage = match &imspection.image {
-            ImageKind::OneChannel(img) => {
-                if let Some(thr_img) = apply_threshold(&imspection.image, &imspection.thr) {
-                    ColorImage::from_gray([thr_img.width(), thr_img.height()], thr_img.as_slice())
-                } else {
-                    ColorImage::from_gray([img.width(), img.height()], img.as_slice())
-                }
-            }
-            ImageKind::ThreeChannel(img) => {
-                ColorImage::from_rgb([img.width(), img.height()], img.as_slice())
-            }
-        };
+pub fn apply_threshold(image: &ImageKind, thr: &ThrSettings) -> Option<ImageKind> {
+    match image {
+        ImageKind::U8(ChannelsKind::One(img)) => {
+            threshold_image(img, thr).map(|img| ImageKind::U8(ChannelsKind::One(img)))
+        }
+        ImageKind::U16(ChannelsKind::One(img)) => {
+            threshold_image(img, thr).map(|img| ImageKind::U16(ChannelsKind::One(img)))
+        }
+        ImageKind::F32(ChannelsKind::One(img), dtype) => {
+            threshold_image(img, thr).map(|img| ImageKind::F32(ChannelsKind::One(img), *dtype))
+        }
+        _ => None,
+    }
+}
+
+/// Maps pixel values to 8 bits, stretching `range` to `0..=255`.
+pub fn to_display_u8<T: Pixel>(data: &[T], range: (f32, f32)) -> Vec<u8> {
+    let (lo, hi) = range;
+    let scale = if hi > lo { 255. / (hi - lo) } else { 0. };
+    data.iter()
+        .map(|&v| ((v.to_f32() - lo) * scale).clamp(0., 255.) as u8)
+        .collect()
+}
+
+/// Returns the value range to stretch to 8 bits, see [`DisplayRange`].
+pub fn display_range(image: &ImageKind, display: DisplayRange) -> (f32, f32) {
+    match display {
+        DisplayRange::Auto => image.value_range(),
+        DisplayRange::Dtype => image.dtype().range(),
+        DisplayRange::Window { min, max } => (min, max),
+    }
+}
+
+/// Side of a transparency checkerboard square, in pixels.
+const CHECKER_SIZE: usize = 8;
+
+fn checkerboard(x: usize, y: usize) -> f32 {
+    if (x / CHECKER_SIZE + y / CHECKER_SIZE) % 2 == 0 {
+        204.
+    } else {
+        153.
+    }
+}
+
+/// What transparent regions of gray+alpha and RGBA images are shown over.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Background {
+    #[default]
+    Checkerboard,
+    Solid([u8; 3]),
+}
+
+impl Background {
+    fn at(&self, x: usize, y: usize) -> [f32; 3] {
+        match self {
+            Background::Checkerboard => [checkerboard(x, y); 3],
+            Background::Solid(rgb) => rgb.map(f32::from),
+        }
+    }
+}
+
+/// Shows RGBA pixels as `view` asks, composited ones over `background`.
+fn composite(
+    size: [usize; 2],
+    rgba: &[u8],
+    alpha: &[f32],
+    view: AlphaView,
+    background: Background,
+) -> ColorImage {
+    let rgb: Vec<u8> = rgba
+        .chunks_exact(4)
+        .zip(alpha)
+        .enumerate()
+        .flat_map(|(i, (px, &a))| {
+            let bg = background.at(i % size[0], i / size[0]);
+            [0, 1, 2].map(|c| {
+                let value = px[c] as f32;
+                let shown = match view {
+                    AlphaView::Composite => value * a + bg[c] * (1. - a),
+                    AlphaView::Straight => value,
+                    AlphaView::Premultiplied => value * a,
+                };
+                shown as u8
+            })
+        })
+        .collect();
+    ColorImage::from_rgb(size, &rgb)
+}
+
+/// With `alpha` the last channel of gray+alpha and RGBA images is opacity, shown as asked.
+fn to_color_image(
+    image: &ImageKind,
+    range: (f32, f32),
+    alpha: Option<(AlphaView, Background)>,
+) -> ColorImage {
+    let size = [image.width(), image.height()];
+    let data: Cow<[u8]> = match image {
+        ImageKind::U8(img) => Cow::Borrowed(img.as_slice()),
+        ImageKind::U16(img) => Cow::Owned(to_display_u8(img.as_slice(), range)),
+        ImageKind::F32(img, _) => Cow::Owned(to_display_u8(img.as_slice(), range)),
+    };
 
-        let options = TextureOptions {
-            magnification: TextureFilter::Nearest,
-            minification: TextureFilter::Nearest,
-            ..Default::default()
+    let alpha = alpha.and_then(|shown| Some((image.alpha()?, shown)));
+    match (image.num_channels(), alpha) {
+        (1, _) => ColorImage::from_gray(size, &data),
+        (2, Some((alpha, (view, background)))) => {
+            let rgba: Vec<u8> = data
+                .chunks_exact(2)
+                .flat_map(|px| [px[0], px[0], px[0], px[1]])
+                .collect();
+            composite(size, &rgba, &alpha, view, background)
+        }
+        (2, None) => {
+            // show the two channels as red and green
+            let rgb: Vec<u8> = data
+                .chunks_exact(2)
+                .flat_map(|px| [px[0], px[1], 0])
+                .collect();
+            ColorImage::from_rgb(size, &rgb)
+        }
+        (3, _) => ColorImage::from_rgb(size, &data),
+        (_, Some((alpha, (view, background)))) => composite(size, &data, &alpha, view, background),
+        (_, None) => ColorImage::from_rgba_unmultiplied(size, &data),
+    }
+}
+
+/// Longest side of a thumbnail, in pixels.
+pub const THUMBNAIL_SIZE: usize = 96;
+
+/// Downscaled preview of the image, stretched to its actual range of values.
+pub fn thumbnail_image(image: &ImageKind, alpha: bool) -> ColorImage {
+    let alpha = alpha.then_some((AlphaView::Composite, Background::Checkerboard));
+    let full = to_color_image(image, display_range(image, DisplayRange::Auto), alpha);
+    if full.pixels.is_empty() {
+        return ColorImage::new([1, 1], Color32::TRANSPARENT);
+    };
+    let [w, h] = full.size;
+    let scale = (THUMBNAIL_SIZE as f32 / w.max(h) as f32).min(1.);
+    let tw = ((w as f32 * scale) as usize).max(1);
+    let th = ((h as f32 * scale) as usize).max(1);
+
+    let pixels = (0..th)
+        .flat_map(|y| (0..tw).map(move |x| (y * h / th) * w + x * w / tw))
+        .map(|i| full.pixels[i])
+        .collect();
+    ColorImage {
+        size: [tw, th],
+        pixels,
+    }
+}
+
+/// Range the panel stretches to 8 bits, of `range_of` with the threshold of the panel applied.
+/// Parts of an image take it from the whole, so that they look like it.
+fn panel_range(imspection: &SingleImspection, range_of: &ImageKind) -> (f32, f32) {
+    let thr_img = apply_threshold(range_of, &imspection.thr);
+    imspection.display_range(thr_img.as_ref().unwrap_or(range_of))
+}
+
+/// `image` with the threshold, `range` and alpha view of the panel.
+fn panel_color_image(
+    imspection: &SingleImspection,
+    image: &ImageKind,
+    range: (f32, f32),
+    background: Background,
+) -> ColorImage {
+    let thr_img = apply_threshold(image, &imspection.thr);
+    let shown = thr_img.as_ref().unwrap_or(image);
+    let alpha = imspection
+        .alpha
+        .then_some((imspection.alpha_view, background));
+    to_color_image(shown, range, alpha)
+}
+
+/// `background` shows through transparent regions of composited images.
+pub fn prepare_texture(
+    ctx: &egui::Context,
+    imspection: &mut SingleImspection,
+    background: Background,
+) {
+    if imspection.need_rerender {
+        imspection.update_stack_range();
+        let size = [imspection.image.width(), imspection.image.height()];
+        let max_side = ctx.input(|input| input.max_texture_side);
+        let color_img = if TiledTexture::needed(size, max_side) {
+            // only the coarsest level is made here, see `prepare_tiles` for the others
+            let mut tiles = TiledTexture::new(size, max_side);
+            let overview = imspection
+                .image
+                .sample(&tiles.overview_region())
+                .expect("the overview lies within the image");
+            tiles.range = panel_range(imspection, &overview);
+            let color_img = panel_color_image(imspection, &overview, tiles.range, background);
+            imspection.tiles = Some(tiles);
+            color_img
+        } else {
+            imspection.tiles = None;
+            let range = panel_range(imspection, &imspection.image);
+            panel_color_image(imspection, &imspection.image, range, background)
         };
 
         if let Some(texture) = &mut imspection.texture {
-            texture.set(color_img, options);
+            texture.set(color_img, TextureOptions::NEAREST);
         } else {
-            imspection.texture =
-                Some(ctx.load_texture(format!("texture_{}", &imspection.id), color_img, options));
+            imspection.texture = Some(ctx.load_texture(
+                format!("texture_{}", &imspection.id),
+                color_img,
+                TextureOptions::NEAREST,
+            ));
         };
+        if let Some(view) = &mut imspection.paged {
+            // read again with the new settings
+            view.clear_detail();
+        };
+        imspection.need_rerender = false;
+    };
+}
+
+/// Samples and uploads the tiles in view of an image too large for one texture,
+/// see [`TiledTexture::update`].
+pub fn prepare_tiles(
+    ctx: &egui::Context,
+    imspection: &mut SingleImspection,
+    visible: [f64; 4],
+    screen_width: f32,
+    background: Background,
+) {
+    let Some(mut tiles) = imspection.tiles.take() else {
+        return;
+    };
+    let range = tiles.range;
+    let uploaded = tiles.update(ctx, imspection.id, visible, screen_width, |region| {
+        let tile = imspection
+            .image
+            .sample(region)
+            .expect("tiles lie within the image");
+        panel_color_image(imspection, &tile, range, background)
+    });
+    imspection.tiles = Some(tiles);
+    if uploaded {
+        ctx.request_repaint();
+    };
+}
+
+/// Reads the visible part of a paged array in the background once it is zoomed in past its
+/// overview, see [`crate::imspect_app::paged_view::PagedView::visible_region`].
+pub fn prepare_detail(
+    ctx: &egui::Context,
+    imspection: &mut SingleImspection,
+    visible: [f64; 4],
+    screen_width: f32,
+    background: Background,
+) {
+    let Some(view) = &mut imspection.paged else {
+        return;
+    };
+    let Some(visible) = view.visible_region(visible, screen_width) else {
+        view.clear_detail();
+        return;
+    };
+    let Some((region, detail)) = view.poll_detail(ctx, visible) else {
+        return;
+    };
+    let range = panel_range(imspection, &imspection.image);
+    let color_img = panel_color_image(imspection, &detail, range, background);
+    let texture = ctx.load_texture(
+        format!("detail_{}", &imspection.id),
+        color_img,
+        TextureOptions::NEAREST,
+    );
+    if let Some(view) = &mut imspection.paged {
+        view.detail = Some((region, texture));
     };
+    ctx.request_repaint();
 }
@@ -0,0 +1,251 @@
+//! Images larger than a GPU texture can be, drawn as tiles of a mip pyramid.
+//! Tiles are sampled from the image once they are in view at the level that matches the zoom,
+//! so no level is ever made whole.
+
+use std::collections::HashMap;
+
+use eframe::epaint::textures::TextureOptions;
+use eframe::epaint::{ColorImage, TextureHandle, TextureId};
+
+use crate::paged::Region;
+
+/// Side of a tile in pixels of its level, at most the largest texture side.
+const TILE_SIZE: usize = 2048;
+
+/// Level, column and row of a tile.
+type TileKey = (usize, usize, usize);
+
+pub struct TiledTexture {
+    /// Width and height of the image.
+    size: [usize; 2],
+    /// Level `k` samples every `2^k`-th pixel, the last one fits in a single tile.
+    levels: usize,
+    tile_size: usize,
+    /// Values stretched to 8 bits in every tile, taken from the overview like for paged arrays.
+    pub range: (f32, f32),
+    /// Tiles uploaded for the current view.
+    tiles: HashMap<TileKey, TextureHandle>,
+}
+
+impl TiledTexture {
+    /// Whether an image of `size` is larger than a single texture can be.
+    pub fn needed(size: [usize; 2], max_texture_side: usize) -> bool {
+        size[0] > max_texture_side || size[1] > max_texture_side
+    }
+
+    pub fn new(size: [usize; 2], max_texture_side: usize) -> Self {
+        let tile_size = TILE_SIZE.min(max_texture_side);
+        let mut levels = 1;
+        while size[0].max(size[1]).div_ceil(1 << (levels - 1)) > tile_size {
+            levels += 1;
+        }
+        Self {
+            size,
+            levels,
+            tile_size,
+            range: (0., 0.),
+            tiles: HashMap::new(),
+        }
+    }
+
+    /// Pixels of `level` within the image region they are sampled from.
+    fn region(&self, level: usize, x: usize, y: usize, span: usize) -> Region {
+        Region {
+            x,
+            y,
+            width: span.min(self.size[0] - x),
+            height: span.min(self.size[1] - y),
+            step: 1 << level,
+        }
+    }
+
+    /// Samples of the coarsest level, drawn under the tiles.
+    pub fn overview_region(&self) -> Region {
+        self.region(self.levels - 1, 0, 0, self.size[0].max(self.size[1]))
+    }
+
+    /// Width and height the coarsest level covers, in pixels of the image.
+    pub fn overview_extent(&self) -> [f32; 2] {
+        let region = self.overview_region();
+        let size = region.sampled_size();
+        let scale = region.step as f32;
+        [size.width as f32 * scale, size.height as f32 * scale]
+    }
+
+    /// Samples of a tile, see [`TileKey`].
+    fn tile_region(&self, (level, column, row): TileKey) -> Region {
+        let span = self.tile_size << level;
+        self.region(level, column * span, row * span, span)
+    }
+
+    /// Level with about one of its pixels per screen pixel.
+    fn level_for(&self, visible_width: f64, screen_width: f32) -> usize {
+        let per_screen_pixel = (visible_width / screen_width.max(1.) as f64).max(1.);
+        (per_screen_pixel.log2().floor() as usize).min(self.levels - 1)
+    }
+
+    /// Tiles of `level` that overlap `[x0, y0, x1, y1]`, given in pixels of the image.
+    fn tiles_in(&self, level: usize, visible: [f64; 4]) -> Vec<TileKey> {
+        let span = self.tile_size << level;
+        let columns = self.size[0].div_ceil(span);
+        let rows = self.size[1].div_ceil(span);
+        let span = span as f64;
+        let first = |value: f64| (value / span).floor().max(0.) as usize;
+        let last = |value: f64, count: usize| ((value / span).ceil().max(0.) as usize).min(count);
+        let [x0, y0, x1, y1] = visible;
+        (first(y0)..last(y1, rows))
+            .flat_map(|row| (first(x0)..last(x1, columns)).map(move |column| (level, column, row)))
+            .collect()
+    }
+
+    /// Uploads the tiles needed to show `visible` `screen_width` pixels wide, and drops the others.
+    /// `render` colors the samples of a region, it's only called for tiles not uploaded yet.
+    /// Returns whether new tiles were uploaded.
+    pub fn update(
+        &mut self,
+        ctx: &egui::Context,
+        id: usize,
+        visible: [f64; 4],
+        screen_width: f32,
+        render: impl Fn(&Region) -> ColorImage,
+    ) -> bool {
+        let level = self.level_for(visible[2] - visible[0], screen_width);
+        // the coarsest level is already drawn whole
+        let needed = if level + 1 < self.levels {
+            self.tiles_in(level, visible)
+        } else {
+            Vec::new()
+        };
+        self.tiles.retain(|key, _| needed.contains(key));
+
+        let mut uploaded = false;
+        for key in needed {
+            if self.tiles.contains_key(&key) {
+                continue;
+            };
+            let tile = render(&self.tile_region(key));
+            let (level, column, row) = key;
+            let name = format!("tile_{}_{}_{}_{}", id, level, column, row);
+            let texture = ctx.load_texture(name, tile, TextureOptions::NEAREST);
+            self.tiles.insert(key, texture);
+            uploaded = true;
+        }
+        uploaded
+    }
+
+    /// Uploaded tiles with the `[x, y, width, height]` they cover in pixels of the image.
+    pub fn shown_tiles(&self) -> Vec<([f32; 4], TextureId)> {
+        self.tiles
+            .iter()
+            .map(|(&(level, column, row), texture)| {
+                let scale = (1 << level) as f32;
+                let [w, h] = texture.size();
+                let origin = (self.tile_size << level) as f32;
+                let rect = [
+                    column as f32 * origin,
+                    row as f32 * origin,
+                    w as f32 * scale,
+                    h as f32 * scale,
+                ];
+                (rect, texture.id())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_end_in_a_single_tile() {
+        assert_eq!(TiledTexture::new([10000, 5000], 16384).levels, 4);
+        assert_eq!(TiledTexture::new([4097, 100], 16384).levels, 3);
+        assert_eq!(TiledTexture::new([100, 4096], 16384).levels, 2);
+        // tiles are never larger than a texture can be
+        let small = TiledTexture::new([5000, 100], 1024);
+        assert_eq!((small.tile_size, small.levels), (1024, 4));
+    }
+
+    #[test]
+    fn overview_covers_the_image() {
+        let tiles = TiledTexture::new([10001, 5000], 16384);
+        let overview = tiles.overview_region();
+        assert_eq!((overview.x, overview.y, overview.step), (0, 0, 8));
+        assert_eq!((overview.width, overview.height), (10001, 5000));
+        assert_eq!(
+            (
+                overview.sampled_size().width,
+                overview.sampled_size().height
+            ),
+            (1251, 625)
+        );
+        assert_eq!(tiles.overview_extent(), [10008., 5000.]);
+    }
+
+    #[test]
+    fn level_for_matches_the_zoom() {
+        let tiles = TiledTexture::new([10000, 5000], 16384);
+        // one image pixel or less per screen pixel
+        assert_eq!(tiles.level_for(500., 1000.), 0);
+        assert_eq!(tiles.level_for(1000., 1000.), 0);
+        assert_eq!(tiles.level_for(1999., 1000.), 0);
+        assert_eq!(tiles.level_for(2000., 1000.), 1);
+        assert_eq!(tiles.level_for(4000., 1000.), 2);
+        // never past the coarsest level
+        assert_eq!(tiles.level_for(1e6, 1000.), 3);
+        // a panel of no width shows the image at its coarsest
+        assert_eq!(tiles.level_for(10000., 0.), 3);
+    }
+
+    #[test]
+    fn tiles_in_the_visible_rectangle() {
+        let tiles = TiledTexture::new([10000, 5000], 16384);
+        assert_eq!(tiles.tiles_in(0, [0., 0., 100., 100.]), vec![(0, 0, 0)]);
+        assert_eq!(
+            tiles.tiles_in(0, [2000., 4000., 2100., 4200.]),
+            vec![(0, 0, 1), (0, 1, 1), (0, 0, 2), (0, 1, 2)]
+        );
+        // a tile of level 1 spans 4096 pixels of the image
+        assert_eq!(
+            tiles.tiles_in(1, [-500., -500., 5000., 100.]),
+            vec![(1, 0, 0), (1, 1, 0)]
+        );
+        // the view past the image edges only takes the tiles that exist
+        assert_eq!(
+            tiles.tiles_in(1, [9000., 4500., 20000., 9000.]),
+            vec![(1, 2, 1)]
+        );
+        assert!(tiles.tiles_in(0, [11000., 0., 12000., 100.]).is_empty());
+    }
+
+    #[test]
+    fn tile_regions_sample_their_level() {
+        let tiles = TiledTexture::new([10000, 5000], 16384);
+        assert_eq!(
+            tiles.tile_region((0, 1, 2)),
+            Region {
+                x: 2048,
+                y: 4096,
+                width: 2048,
+                height: 904,
+                step: 1,
+            }
+        );
+        let edge = tiles.tile_region((1, 2, 1));
+        assert_eq!(
+            edge,
+            Region {
+                x: 8192,
+                y: 4096,
+                width: 1808,
+                height: 904,
+                step: 2,
+            }
+        );
+        assert_eq!(
+            (edge.sampled_size().width, edge.sampled_size().height),
+            (904, 452)
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Reloading of files that are overwritten while the window is open.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use std::sync::Arc;
+
+use crate::imspect_app::image_kind::ImageKind;
+use crate::input::{bgr_to_rgb, load_image};
+use crate::paged::PagedNpy;
+use crate::yuv::YuvFrame;
+use crate::{npz, raw};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+pub struct Reloaded {
+    pub path: PathBuf,
+    pub image: ImageKind,
+    pub stack: Option<Vec<ImageKind>>,
+    pub yuv: Option<Vec<YuvFrame>>,
+    pub paged: Option<Arc<PagedNpy>>,
+}
+
+/// Modification time and length, a rewrite within the mtime resolution usually changes the length.
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    // raw buffers and archived arrays change through the file that holds them
+    let file = match (raw::split_arg(path), npz::split_member(path)) {
+        (Some((file, _)), _) => file,
+        (None, Some((archive, _))) if !path.exists() => archive.to_owned(),
+        _ => path.to_owned(),
+    };
+    let metadata = fs::metadata(file).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Files reloaded in a background thread, see [`watch`].
+pub struct Watcher {
+    pub reloads: Receiver<Reloaded>,
+    added: Sender<PathBuf>,
+}
+
+impl Watcher {
+    /// Also reloads `path` from now on, paths already watched are left as they are.
+    pub fn add(&self, path: &Path) {
+        let _ = self.added.send(path.to_owned());
+    }
+}
+
+struct Watched {
+    path: PathBuf,
+    /// Stamp of the version loaded last.
+    loaded: Option<(SystemTime, u64)>,
+    /// Stamp of a version that failed to decode, it isn't read again.
+    failed: Option<(SystemTime, u64)>,
+}
+
+/// Polls the files added to the watcher and sends their new contents when they change.
+pub fn watch(ctx: egui::Context, bgr: bool) -> Watcher {
+    let (sender, receiver) = mpsc::channel();
+    let (added, new_paths) = mpsc::channel::<PathBuf>();
+
+    thread::spawn(move || {
+        let mut watched: Vec<Watched> = Vec::new();
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            for path in new_paths.try_iter() {
+                if watched.iter().all(|file| file.path != path) {
+                    watched.push(Watched {
+                        loaded: stamp(&path),
+                        failed: None,
+                        path,
+                    });
+                };
+            }
+            for file in watched.iter_mut() {
+                let current = stamp(&file.path);
+                if current.is_none() || current == file.loaded || current == file.failed {
+                    continue;
+                };
+                // a file that is still being written fails to decode,
+                // it's read again once writing it changes its stamp
+                let Ok(img) = load_image(&file.path) else {
+                    file.failed = current;
+                    continue;
+                };
+                file.loaded = current;
+                let img = if bgr { bgr_to_rgb(img) } else { img };
+                let reloaded = Reloaded {
+                    path: file.path.clone(),
+                    image: img.image,
+                    stack: img.stack,
+                    yuv: img.yuv,
+                    paged: img.paged,
+                };
+                if sender.send(reloaded).is_err() {
+                    // the window is closed
+                    return;
+                };
+                ctx.request_repaint();
+            }
+        }
+    });
+    Watcher {
+        reloads: receiver,
+        added,
+    }
+}
@@ -1,52 +1,625 @@
-use anyhow::Context;
-use std::path::PathBuf;
+use anyhow::{anyhow, Context};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use kornia::image::{Image, ImageError, ImageSize};
-use kornia::io::functional::read_image_any;
-use kornia::io::IoError;
-use ndarray::Array3;
-use ndarray_npy::read_npy;
+use image::{DynamicImage, ImageFormat};
+use kornia::image::{ImageError, ImageSize};
+use ndarray::{Array3, ArrayD, Axis, Ix3};
+use ndarray_npy::{ReadNpyError, ReadNpyExt, ReadableElement};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
 
-use crate::imspect_app::imspection::ImageKind;
+use crate::format::Format;
+use crate::imspect_app::image_kind::{
+    ChannelsKind, ColorSpaceChange, DType, ImageKind, NamedImage, Pixel,
+};
+use crate::npz;
+use crate::paged::{PagedNpy, PAGED_MIN_LEN, PREVIEW_SIDE};
+use crate::raw::{self, RawSpec};
+use crate::yuv::YuvSpec;
 
-pub fn load_images(args: Vec<PathBuf>) -> Result<Vec<ImageKind>, anyhow::Error> {
-    let mut imgs = Vec::with_capacity(args.len());
+/// Reads the array only if its dtype matches `T`, split into HxWxC slices.
+/// 2-D arrays get a channel axis, the same way `_imspect_for_shell` treats them.
+/// 3-D arrays with more than 4 values on the last axis are volumes, sliced along the
+/// shorter of the first (DxHxW) and last (HxWxD) axes. 4-D arrays are DxHxWxC.
+fn read_npy_as<T: ReadableElement + Clone>(
+    bytes: &[u8],
+) -> Result<Option<Vec<Array3<T>>>, anyhow::Error> {
+    let arr = match ArrayD::<T>::read_npy(bytes) {
+        Ok(arr) => arr,
+        Err(ReadNpyError::WrongDescriptor(_)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let shape = arr.shape().to_vec();
+    if shape.contains(&0) {
+        return Err(anyhow!("The array is empty, shaped {:?}", shape));
+    };
+    let slices = match shape.len() {
+        2 => vec![arr.insert_axis(Axis(2)).into_dimensionality::<Ix3>()?],
+        3 if shape[2] <= 4 => vec![arr.into_dimensionality::<Ix3>()?],
+        3 => {
+            let axis = if shape[0] <= shape[2] { 0 } else { 2 };
+            arr.axis_iter(Axis(axis))
+                .map(|slice| slice.insert_axis(Axis(2)).to_owned())
+                .map(|slice| slice.into_dimensionality::<Ix3>())
+                .collect::<Result<_, _>>()?
+        }
+        4 => arr
+            .axis_iter(Axis(0))
+            .map(|slice| slice.to_owned().into_dimensionality::<Ix3>())
+            .collect::<Result<_, _>>()?,
+        ndim => {
+            return Err(anyhow!(
+                "Only arrays with 2 to 4 dimensions can be accepted, got {}",
+                ndim
+            ))
+        }
+    };
+    if slices.is_empty() {
+        return Err(anyhow!("The array has no slices"));
+    };
+    Ok(Some(slices))
+}
+
+fn channels_from_array<T: Pixel>(arr: Array3<T>) -> Result<ChannelsKind<T>, ImageError> {
+    let (h, w, c) = arr.dim();
+    // Fortran-ordered arrays are stored column by column
+    let arr = if arr.is_standard_layout() {
+        arr
+    } else {
+        arr.as_standard_layout().into_owned()
+    };
+    let image_size = ImageSize {
+        width: w,
+        height: h,
+    };
+    ChannelsKind::new(image_size, c, arr.into_raw_vec_and_offset().0)
+}
+
+/// Keeps `dtype` in the image while storing the values as `f32`.
+fn widened_image<T: Copy>(
+    arr: Array3<T>,
+    dtype: DType,
+    to_f32: impl Fn(T) -> f32,
+) -> Result<ImageKind, ImageError> {
+    Ok(ImageKind::F32(
+        channels_from_array(arr.mapv(to_f32))?,
+        dtype,
+    ))
+}
+
+/// Converts every slice of the array with `to_image`.
+fn npy_slices<T: ReadableElement + Clone>(
+    bytes: &[u8],
+    to_image: impl Fn(Array3<T>) -> Result<ImageKind, ImageError>,
+) -> Result<Option<Vec<ImageKind>>, anyhow::Error> {
+    let Some(slices) = read_npy_as::<T>(bytes)? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        slices.into_iter().map(to_image).collect::<Result<_, _>>()?,
+    ))
+}
+
+/// Decodes a serialized `.npy` array of any supported dtype, keeping the original values.
+/// Volumes give one image per slice.
+fn load_npy_slices(bytes: &[u8]) -> Result<Vec<ImageKind>, anyhow::Error> {
+    if let Some(slices) =
+        npy_slices::<u8>(bytes, |arr| Ok(ImageKind::U8(channels_from_array(arr)?)))?
+    {
+        return Ok(slices);
+    }
+    if let Some(slices) =
+        npy_slices::<u16>(bytes, |arr| Ok(ImageKind::U16(channels_from_array(arr)?)))?
+    {
+        return Ok(slices);
+    }
+    if let Some(slices) = npy_slices::<f32>(bytes, |arr| {
+        Ok(ImageKind::F32(channels_from_array(arr)?, DType::F32))
+    })? {
+        return Ok(slices);
+    }
+    if let Some(slices) = npy_slices::<bool>(bytes, |arr| {
+        widened_image(arr, DType::Bool, |v| v as u8 as f32)
+    })? {
+        return Ok(slices);
+    }
+    if let Some(slices) =
+        npy_slices::<i8>(bytes, |arr| widened_image(arr, DType::I8, |v| v as f32))?
+    {
+        return Ok(slices);
+    }
+    if let Some(slices) =
+        npy_slices::<i16>(bytes, |arr| widened_image(arr, DType::I16, |v| v as f32))?
+    {
+        return Ok(slices);
+    }
+    if let Some(slices) =
+        npy_slices::<u32>(bytes, |arr| widened_image(arr, DType::U32, |v| v as f32))?
+    {
+        return Ok(slices);
+    }
+    if let Some(slices) =
+        npy_slices::<i32>(bytes, |arr| widened_image(arr, DType::I32, |v| v as f32))?
+    {
+        return Ok(slices);
+    }
+    if let Some(slices) =
+        npy_slices::<i64>(bytes, |arr| widened_image(arr, DType::I64, |v| v as f32))?
+    {
+        return Ok(slices);
+    }
+    if let Some(slices) =
+        npy_slices::<f64>(bytes, |arr| widened_image(arr, DType::F64, |v| v as f32))?
+    {
+        return Ok(slices);
+    }
+    Err(anyhow!(
+        "Unsupported dtype, expected bool, an integer type up to 64 bits, float32 or float64"
+    ))
+}
+
+/// Decodes a serialized `.npy` array holding a single image.
+pub fn load_npy_bytes(bytes: &[u8]) -> Result<ImageKind, anyhow::Error> {
+    let mut slices = load_npy_slices(bytes)?;
+    if slices.len() > 1 {
+        return Err(anyhow!(
+            "Expected a single image, got a stack of {} slices",
+            slices.len()
+        ));
+    };
+    Ok(slices.remove(0))
+}
+
+/// Decodes every page of a multi-page TIFF, `None` for single-page files.
+fn load_tiff_pages(bytes: &[u8]) -> Result<Option<Vec<ImageKind>>, anyhow::Error> {
+    let mut decoder = Decoder::new(Cursor::new(bytes))?;
+    if !decoder.more_images() {
+        return Ok(None);
+    };
+    let mut pages = Vec::new();
+    loop {
+        let page = tiff_page(&mut decoder).with_context(|| format!("Page {}", pages.len() + 1))?;
+        pages.push(page);
+        if !decoder.more_images() {
+            break;
+        };
+        decoder.next_image()?;
+    }
+    Ok(Some(pages))
+}
+
+fn tiff_page(decoder: &mut Decoder<Cursor<&[u8]>>) -> Result<ImageKind, anyhow::Error> {
+    let (width, height) = decoder.dimensions()?;
+    let size = ImageSize {
+        width: width as usize,
+        height: height as usize,
+    };
+    let channels = match decoder.colortype()? {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) => 3,
+        ColorType::RGBA(_) => 4,
+        ColorType::Multiband { num_samples, .. } if num_samples <= 4 => num_samples as usize,
+        other => return Err(anyhow!("Unsupported TIFF color type {:?}", other)),
+    };
+    let widened = |data: Vec<f32>, dtype| -> Result<ImageKind, ImageError> {
+        Ok(ImageKind::F32(
+            ChannelsKind::new(size, channels, data)?,
+            dtype,
+        ))
+    };
+    let image = match decoder.read_image()? {
+        DecodingResult::U8(data) => ImageKind::U8(ChannelsKind::new(size, channels, data)?),
+        DecodingResult::U16(data) => ImageKind::U16(ChannelsKind::new(size, channels, data)?),
+        DecodingResult::F32(data) => widened(data, DType::F32)?,
+        DecodingResult::F64(data) => {
+            widened(data.into_iter().map(|v| v as f32).collect(), DType::F64)?
+        }
+        DecodingResult::I8(data) => {
+            widened(data.into_iter().map(|v| v as f32).collect(), DType::I8)?
+        }
+        DecodingResult::I16(data) => {
+            widened(data.into_iter().map(|v| v as f32).collect(), DType::I16)?
+        }
+        DecodingResult::U32(data) => {
+            widened(data.into_iter().map(|v| v as f32).collect(), DType::U32)?
+        }
+        DecodingResult::I32(data) => {
+            widened(data.into_iter().map(|v| v as f32).collect(), DType::I32)?
+        }
+        DecodingResult::I64(data) => {
+            widened(data.into_iter().map(|v| v as f32).collect(), DType::I64)?
+        }
+        _ => return Err(anyhow!("Unsupported TIFF sample format")),
+    };
+    Ok(image)
+}
+
+/// Panel title of an image loaded from a file.
+pub fn file_title(img_path: &Path) -> String {
+    img_path
+        .file_name()
+        .unwrap_or(img_path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A file that couldn't be opened, reported next to the images that could.
+pub struct LoadError {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {:#}", self.path.display(), self.error)
+    }
+}
+
+/// Path that stands for stdin on the command line.
+pub const STDIN: &str = "-";
+
+fn is_npy(bytes: &[u8]) -> bool {
+    Format::sniff(bytes) == Some(Format::Npy)
+}
 
-    for img_path in &args {
-        // Check for valid file extension
-        let extension = img_path
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .ok_or_else(|| IoError::InvalidFileExtension(img_path.to_owned()))?;
-
-        if extension == "npy" {
-            // Handle .npy file
-            let arr: Array3<u8> = read_npy(img_path)
-                .with_context(|| format!("Failed to read npy file: {:?}", img_path))?;
-            let (h, w, c) = arr.dim();
-
-            // Match the channel count to determine image type
-            let image_size = ImageSize {
-                width: w,
-                height: h,
+/// Keeps the channels and depth of the file: gray, gray+alpha, RGB and RGBA,
+/// 8-bit, 16-bit or float. Other layouts are decoded as 8-bit RGB.
+fn from_dynamic(img: DynamicImage) -> Result<ImageKind, anyhow::Error> {
+    let size = ImageSize {
+        width: img.width() as usize,
+        height: img.height() as usize,
+    };
+    Ok(match img {
+        DynamicImage::ImageLuma8(gray) => {
+            ImageKind::U8(ChannelsKind::new(size, 1, gray.into_raw())?)
+        }
+        DynamicImage::ImageLumaA8(gray) => {
+            ImageKind::U8(ChannelsKind::new(size, 2, gray.into_raw())?)
+        }
+        DynamicImage::ImageRgb8(rgb) => ImageKind::U8(ChannelsKind::new(size, 3, rgb.into_raw())?),
+        DynamicImage::ImageRgba8(rgba) => {
+            ImageKind::U8(ChannelsKind::new(size, 4, rgba.into_raw())?)
+        }
+        DynamicImage::ImageLuma16(gray) => {
+            ImageKind::U16(ChannelsKind::new(size, 1, gray.into_raw())?)
+        }
+        DynamicImage::ImageLumaA16(gray) => {
+            ImageKind::U16(ChannelsKind::new(size, 2, gray.into_raw())?)
+        }
+        DynamicImage::ImageRgb16(rgb) => {
+            ImageKind::U16(ChannelsKind::new(size, 3, rgb.into_raw())?)
+        }
+        DynamicImage::ImageRgba16(rgba) => {
+            ImageKind::U16(ChannelsKind::new(size, 4, rgba.into_raw())?)
+        }
+        DynamicImage::ImageRgb32F(rgb) => {
+            ImageKind::F32(ChannelsKind::new(size, 3, rgb.into_raw())?, DType::F32)
+        }
+        DynamicImage::ImageRgba32F(rgba) => {
+            ImageKind::F32(ChannelsKind::new(size, 4, rgba.into_raw())?, DType::F32)
+        }
+        _ => ImageKind::U8(ChannelsKind::new(size, 3, img.into_rgb8().into_raw())?),
+    })
+}
+
+/// Decodes an image held in memory, picking the decoder by its content.
+/// The extension of `path` is only a fallback for formats that aren't sniffed.
+/// Multi-page files and volumes give every slice.
+fn load_image_bytes(bytes: &[u8], path: Option<&Path>) -> Result<Vec<ImageKind>, anyhow::Error> {
+    match Format::sniff(bytes) {
+        Some(Format::Npy) => load_npy_slices(bytes).context("Failed to read NPY array"),
+        Some(format) => {
+            if format == Format::Tiff {
+                if let Some(pages) =
+                    load_tiff_pages(bytes).context("Failed to decode TIFF pages")?
+                {
+                    return Ok(pages);
+                };
             };
-            let data = arr.into_raw_vec_and_offset().0;
+            let image_format = format
+                .image_format()
+                .ok_or_else(|| anyhow!("Detected a {} file, which isn't supported", format))?;
+            let img = image::load_from_memory_with_format(bytes, image_format)
+                .with_context(|| format!("Failed to decode {} image", format))?;
+            Ok(vec![from_dynamic(img)?])
+        }
+        None => {
+            let guessed = image::guess_format(bytes)
+                .ok()
+                .or_else(|| path.and_then(|path| ImageFormat::from_path(path).ok()));
+            let image_format = guessed.ok_or_else(|| match path.and_then(|p| p.extension()) {
+                Some(ext) if ext == "raw" || ext == "bin" => anyhow!(
+                    "Headerless files need their layout after the path, e.g. {}:640x480:u16le",
+                    path.map(file_title).unwrap_or_default()
+                ),
+                _ => anyhow!("Unrecognized file format"),
+            })?;
+            let img = image::load_from_memory_with_format(bytes, image_format)
+                .with_context(|| format!("Failed to decode {:?} image", image_format))?;
+            Ok(vec![from_dynamic(img)?])
+        }
+    }
+}
+
+/// RGBA images and gray+alpha ones decoded from image files have opacity in their last channel,
+/// two-channel arrays are left as they are.
+fn has_alpha(image: &ImageKind, bytes: &[u8]) -> bool {
+    match image.num_channels() {
+        2 => !is_npy(bytes),
+        4 => true,
+        _ => false,
+    }
+}
+
+/// Names the decoded slices, a single one isn't a stack.
+fn named_image(
+    title: String,
+    mut slices: Vec<ImageKind>,
+    alpha: bool,
+    path: Option<PathBuf>,
+) -> NamedImage {
+    let (image, stack) = if slices.len() == 1 {
+        (slices.remove(0), None)
+    } else {
+        (slices[0].clone(), Some(slices))
+    };
+    NamedImage {
+        title,
+        image,
+        stack,
+        yuv: None,
+        alpha,
+        path,
+        paged: None,
+        decoded: false,
+    }
+}
+
+/// Reads a piped image, `npy` requires it to be a `.npy` array.
+fn load_stdin(npy: bool) -> Result<NamedImage, anyhow::Error> {
+    let mut bytes = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut bytes)
+        .context("Failed to read stdin")?;
+    if npy && !is_npy(&bytes) {
+        return Err(anyhow!("Expected a .npy array on stdin"));
+    };
+    let slices = load_image_bytes(&bytes, None)?;
+    let alpha = has_alpha(&slices[0], &bytes);
+    Ok(NamedImage {
+        decoded: !is_npy(&bytes),
+        ..named_image("stdin".to_string(), slices, alpha, None)
+    })
+}
+
+/// Converts every frame of a YUV file to RGB, keeping the frames for their planes.
+fn load_yuv(img_path: &Path, file: &Path, spec: YuvSpec) -> Result<NamedImage, anyhow::Error> {
+    let frames = spec.frames(&fs::read(file)?)?;
+    let slices = frames
+        .iter()
+        .map(|frame| frame.to_rgb())
+        .collect::<Result<_, _>>()?;
+    Ok(NamedImage {
+        yuv: Some(frames),
+        decoded: true,
+        ..named_image(
+            file_title(img_path),
+            slices,
+            false,
+            Some(img_path.to_owned()),
+        )
+    })
+}
+
+/// The array saved under `key` in the `.npz` archive held in `bytes`, titled with the key.
+fn npz_member(img_path: &Path, bytes: &[u8], key: &str) -> Result<NamedImage, anyhow::Error> {
+    let npy = npz::member(bytes, key)?;
+    let slices =
+        load_npy_slices(&npy).with_context(|| format!("Failed to read NPY array '{}'", key))?;
+    Ok(named_image(
+        key.to_owned(),
+        slices,
+        false,
+        Some(img_path.to_owned()),
+    ))
+}
+
+/// Every array of an `.npz` archive, the ones that fail are reported with
+/// the `bundle.npz/KEY` path that opens them alone.
+fn load_npz(img_path: &Path) -> Result<Vec<Result<NamedImage, LoadError>>, anyhow::Error> {
+    let bytes = fs::read(img_path)?;
+    let keys = npz::keys(&bytes)?;
+    if keys.is_empty() {
+        return Err(anyhow!("The archive holds no arrays"));
+    };
+    Ok(keys
+        .iter()
+        .map(|key| {
+            let path = img_path.join(key);
+            npz_member(&path, &bytes, key).map_err(|error| LoadError { path, error })
+        })
+        .collect())
+}
+
+/// Opens a paged array with a coarse preview, its overview is made once it is shown.
+fn load_paged(img_path: &Path, paged: PagedNpy) -> Result<NamedImage, anyhow::Error> {
+    let preview = paged.overview(PREVIEW_SIDE)?;
+    Ok(NamedImage {
+        paged: Some(Arc::new(paged)),
+        ..named_image(
+            file_title(img_path),
+            vec![preview],
+            false,
+            Some(img_path.to_owned()),
+        )
+    })
+}
+
+/// Whether the file starts like a zip archive, which is what `np.savez` writes.
+fn is_archive(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && Format::sniff(&magic) == Some(Format::Zip)
+}
+
+pub fn load_image(img_path: &Path) -> Result<NamedImage, anyhow::Error> {
+    if img_path == Path::new(STDIN) {
+        return load_stdin(false);
+    };
+    if let Some((file, spec)) = raw::split_arg(img_path) {
+        if YuvSpec::matches(spec) {
+            return load_yuv(img_path, &file, spec.parse()?);
+        };
+        let spec: RawSpec = spec.parse()?;
+        let image = spec.decode(&fs::read(&file)?)?;
+        return Ok(named_image(
+            file_title(img_path),
+            vec![image],
+            spec.channels == 4,
+            Some(img_path.to_owned()),
+        ));
+    };
+    if let Some((archive, key)) = npz::split_member(img_path) {
+        if !img_path.exists() {
+            return npz_member(img_path, &fs::read(archive)?, &key);
+        };
+    };
+    let file = File::open(img_path)?;
+    if file.metadata()?.len() >= PAGED_MIN_LEN {
+        if let Some(paged) = PagedNpy::open(&file)? {
+            return load_paged(img_path, paged);
+        };
+    };
+    let bytes = fs::read(img_path)?;
+    if Format::sniff(&bytes) == Some(Format::Zip) {
+        // a single panel holds a single array
+        return match npz::keys(&bytes)?.as_slice() {
+            [] => Err(anyhow!("The archive holds no arrays")),
+            [key] => npz_member(&img_path.join(key), &bytes, key),
+            keys => Err(anyhow!(
+                "The archive holds {} arrays, open one as {}",
+                keys.len(),
+                img_path.join(&keys[0]).display()
+            )),
+        };
+    };
+    let slices = load_image_bytes(&bytes, Some(img_path))?;
+    let alpha = has_alpha(&slices[0], &bytes);
+    Ok(NamedImage {
+        decoded: !is_npy(&bytes),
+        ..named_image(
+            file_title(img_path),
+            slices,
+            alpha,
+            Some(img_path.to_owned()),
+        )
+    })
+}
+
+/// Swaps the channels of BGR and BGRA arrays and raw buffers, leaving the others as they are.
+/// Decoded image files are already RGB. Paged arrays swap them as they are read.
+pub fn bgr_to_rgb(mut img: NamedImage) -> NamedImage {
+    if img.decoded {
+        return img;
+    };
+    if let Some(paged) = img.paged.as_mut().and_then(Arc::get_mut) {
+        paged.bgr = true;
+    };
+    let change = match img.image.num_channels() {
+        3 => ColorSpaceChange::BGR2RGB,
+        4 => ColorSpaceChange::BGRA2RGBA,
+        _ => return img,
+    };
+    let Ok(image) = img.image.change_color(change) else {
+        return img;
+    };
+    let stack = match &img.stack {
+        Some(slices) => {
+            let converted = slices.iter().map(|slice| slice.change_color(change));
+            match converted.collect::<Result<_, _>>() {
+                Ok(slices) => Some(slices),
+                Err(_) => return img,
+            }
+        }
+        None => None,
+    };
+    NamedImage {
+        image,
+        stack,
+        ..img
+    }
+}
+
+/// Replaces `.npz` archives by a `bundle.npz/KEY` path per array, so each can be
+/// loaded alone with [`load_image`].
+pub fn expand_archives(paths: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<LoadError>) {
+    let mut expanded = Vec::with_capacity(paths.len());
+    let mut errors = Vec::new();
+    for path in paths {
+        if !is_archive(&path) {
+            expanded.push(path);
+            continue;
+        };
+        match npz::file_keys(&path) {
+            Ok(keys) if !keys.is_empty() => {
+                expanded.extend(keys.iter().map(|key| path.join(key)));
+            }
+            Ok(_) => errors.push(LoadError {
+                path,
+                error: anyhow!("The archive holds no arrays"),
+            }),
+            Err(error) => errors.push(LoadError { path, error }),
+        }
+    }
+    (expanded, errors)
+}
+
+/// Loads every file it can, collecting the errors of the rest.
+/// Every array of an `.npz` archive is an image of its own.
+/// With `stdin_npy` a [`STDIN`] argument must be a `.npy` array.
+pub fn load_images(args: Vec<PathBuf>, stdin_npy: bool) -> (Vec<NamedImage>, Vec<LoadError>) {
+    let mut imgs = Vec::with_capacity(args.len());
+    let mut errors = Vec::new();
 
-            let img = match c {
-                1 => ImageKind::OneChannel(Image::<u8, 1>::new(image_size, data)?),
-                3 => ImageKind::ThreeChannel(Image::<u8, 3>::new(image_size, data)?),
-                _ => return Err(ImageError::InvalidChannelShape(c, 3).into()),
+    for img_path in args {
+        if is_archive(&img_path) {
+            match load_npz(&img_path) {
+                Ok(members) => {
+                    for member in members {
+                        match member {
+                            Ok(img) => imgs.push(img),
+                            Err(error) => errors.push(error),
+                        }
+                    }
+                }
+                Err(error) => errors.push(LoadError {
+                    path: img_path,
+                    error,
+                }),
             };
-            imgs.push(img);
+            continue;
+        };
+        let loaded = if stdin_npy && img_path == Path::new(STDIN) {
+            load_stdin(true)
         } else {
-            // Handle non-.npy file
-            let img = ImageKind::ThreeChannel(
-                read_image_any(img_path)
-                    .with_context(|| format!("Failed to read image file: {:?}", img_path))?,
-            );
-            imgs.push(img);
+            load_image(&img_path)
+        };
+        match loaded {
+            Ok(img) => imgs.push(img),
+            Err(error) => errors.push(LoadError {
+                path: img_path,
+                error,
+            }),
         }
     }
 
-    Ok(imgs)
+    (imgs, errors)
 }
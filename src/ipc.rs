@@ -0,0 +1,417 @@
+//! Handoff of images between the Python process and a viewer process.
+//!
+//! A new viewer reads a message from its stdin. Viewers started that way also keep
+//! listening on a local port, so later calls append to the same window instead of
+//! opening a new one. The port and a random token are written to a file only the user
+//! can read, see [`viewer_file`], and a connection is dropped unless it starts with the token.
+//!
+//! A message is a flags byte, a little-endian `u32` count and that many frames.
+//! A frame is the title of the image, its parameters and the image as a serialized
+//! `.npy` array, each prefixed by its little-endian byte length (`u32`, `u32`, `u64`).
+//! The viewer answers with [`ACK`] once every image is decoded,
+//! so the sender knows the data is owned by the viewer and can return.
+//! With [`FLAG_BLOCK`] the viewer also sends its images back in a message when closed.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Context;
+use kornia::image::ImageSize;
+use ndarray::{ArrayView2, ArrayView3};
+use ndarray_npy::{WritableElement, WriteNpyExt};
+
+use crate::imspect_app::image_kind::{ChannelsKind, DType, ImageKind, NamedImage, Pixel};
+use crate::imspect_app::imspection::SingleImspection;
+use crate::input::load_npy_bytes;
+
+pub const ACK: &[u8] = b"imspect:ok\n";
+
+/// Flag asking the viewer to close its current images first.
+pub const FLAG_REPLACE: u8 = 1;
+/// Flag asking a new viewer to send its images back when closed.
+pub const FLAG_BLOCK: u8 = 2;
+
+pub const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+/// Longest wait for the other side of a connection to the persistent viewer.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+/// Length of the token, in hex digits.
+const TOKEN_LEN: usize = 32;
+
+/// Limits of a message, so that a malformed one fails instead of exhausting memory.
+const MAX_FRAMES: u32 = 1 << 16;
+const MAX_TEXT_LEN: usize = 1 << 20;
+const MAX_NPY_LEN: u64 = 1 << 36;
+/// Bytes allocated ahead of reading an array, larger ones grow as their data comes in.
+const NPY_PREALLOCATED: usize = 1 << 26;
+
+/// File with the port and token of the persistent viewer, in a directory of the user:
+/// `XDG_RUNTIME_DIR`, `LOCALAPPDATA` or the home directory.
+fn viewer_file() -> PathBuf {
+    let dir = ["XDG_RUNTIME_DIR", "LOCALAPPDATA", "HOME"]
+        .into_iter()
+        .find_map(env::var_os)
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    dir.join(".imspect-viewer")
+}
+
+/// Whether only the owner may read and write the file.
+#[cfg(unix)]
+fn is_private(file: &File) -> io::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(file.metadata()?.permissions().mode() & 0o077 == 0)
+}
+
+#[cfg(not(unix))]
+fn is_private(_file: &File) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Address and token of the persistent viewer, if one has written its file.
+/// A file that others could read or write is ignored.
+pub fn find_viewer() -> Option<(SocketAddr, String)> {
+    let mut file = File::open(viewer_file()).ok()?;
+    if !is_private(&file).ok()? {
+        return None;
+    };
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    let (port, token) = contents.trim().split_once(' ')?;
+    if token.len() != TOKEN_LEN {
+        return None;
+    };
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port.parse().ok()?));
+    Some((addr, token.to_owned()))
+}
+
+fn write_viewer_file(port: u16, token: &str) -> io::Result<()> {
+    let path = viewer_file();
+    // created anew, so that it has the permissions set below
+    let _ = fs::remove_file(&path);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    };
+    let mut file = options.open(path)?;
+    if !is_private(&file)? {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "The viewer file is readable by other users",
+        ));
+    };
+    writeln!(file, "{} {}", port, token)
+}
+
+fn new_token() -> io::Result<String> {
+    let mut bytes = [0; TOKEN_LEN / 2];
+    getrandom::fill(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub fn set_timeouts(stream: &TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))
+}
+
+pub struct Frame {
+    pub title: String,
+    /// [`Param`] lines describing how the image was made, empty for inputs.
+    pub params: String,
+    pub npy: Vec<u8>,
+}
+
+/// Value of a parameter sent back with an image.
+pub enum Param {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Text(String),
+}
+
+impl Param {
+    /// The `key:type=value` line of the parameter, e.g. `channel:int=2`,
+    /// the type being `bool`, `int`, `float` or `str`.
+    pub fn line(&self, key: &str) -> String {
+        match self {
+            Param::Bool(value) => format!("{}:bool={}\n", key, value),
+            Param::Int(value) => format!("{}:int={}\n", key, value),
+            Param::Float(value) => format!("{}:float={:?}\n", key, value),
+            Param::Text(value) => format!("{}:str={}\n", key, value),
+        }
+    }
+}
+
+pub fn write_frames<W: Write>(writer: &mut W, flags: u8, frames: &[Frame]) -> io::Result<()> {
+    writer.write_all(&[flags])?;
+    writer.write_all(&(frames.len() as u32).to_le_bytes())?;
+    for frame in frames {
+        writer.write_all(&(frame.title.len() as u32).to_le_bytes())?;
+        writer.write_all(frame.title.as_bytes())?;
+        writer.write_all(&(frame.params.len() as u32).to_le_bytes())?;
+        writer.write_all(frame.params.as_bytes())?;
+        writer.write_all(&(frame.npy.len() as u64).to_le_bytes())?;
+        writer.write_all(&frame.npy)?;
+    }
+    writer.flush()
+}
+
+fn too_large(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("The message has too many {}", what),
+    )
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity((len as usize).min(NPY_PREALLOCATED));
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    };
+    Ok(bytes)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len as usize > MAX_TEXT_LEN {
+        return Err(too_large("bytes in a title"));
+    };
+    let bytes = read_bytes(reader, len.into())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+pub fn read_frames<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<Frame>)> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    let flags = header[0];
+    let count = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    if count > MAX_FRAMES {
+        return Err(too_large("images"));
+    };
+
+    let mut frames = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let title = read_string(reader)?;
+        let params = read_string(reader)?;
+        let mut len = [0; 8];
+        reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        if len > MAX_NPY_LEN {
+            return Err(too_large("bytes in an array"));
+        };
+        let npy = read_bytes(reader, len)?;
+        frames.push(Frame { title, params, npy });
+    }
+    Ok((flags, frames))
+}
+
+pub struct Message {
+    pub replace: bool,
+    pub block: bool,
+    pub images: Vec<NamedImage>,
+}
+
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Message, anyhow::Error> {
+    let (flags, frames) = read_frames(reader)?;
+    let images = frames
+        .into_iter()
+        .map(|frame| {
+            let image = load_npy_bytes(&frame.npy)?;
+            Ok(NamedImage {
+                title: frame.title,
+                alpha: image.num_channels() == 4,
+                image,
+                stack: None,
+                yuv: None,
+                path: None,
+                paged: None,
+                decoded: false,
+            })
+        })
+        .collect::<Result<Vec<_>, anyhow::Error>>()?;
+    Ok(Message {
+        replace: flags & FLAG_REPLACE != 0,
+        block: flags & FLAG_BLOCK != 0,
+        images,
+    })
+}
+
+/// Reads the images sent by `_imspect_for_shell` and acknowledges them.
+pub fn receive_images() -> Result<Message, anyhow::Error> {
+    let message = read_message(&mut io::stdin().lock()).context("Failed to receive images")?;
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(ACK)?;
+    stdout.flush()?;
+    Ok(message)
+}
+
+/// Messages accepted by the persistent viewer. Its file is removed when it's dropped.
+pub struct Incoming {
+    pub messages: Receiver<Message>,
+    token: String,
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        // unless a newer viewer took over
+        if find_viewer().is_some_and(|(_, token)| token == self.token) {
+            let _ = fs::remove_file(viewer_file());
+        };
+    }
+}
+
+/// Whether the connection starts with `token`.
+fn authenticate(stream: &mut TcpStream, token: &str) -> bool {
+    let mut sent = [0; TOKEN_LEN];
+    set_timeouts(stream).is_ok() && stream.read_exact(&mut sent).is_ok() && sent == token.as_bytes()
+}
+
+/// Accepts messages on a local port in a background thread, from connections that
+/// start with the token written next to the port.
+/// Returns `None` if another viewer is already listening.
+pub fn listen(ctx: egui::Context) -> Option<Incoming> {
+    if let Some((addr, _)) = find_viewer() {
+        if TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).is_ok() {
+            return None;
+        };
+    };
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).ok()?;
+    let token = new_token().ok()?;
+    write_viewer_file(listener.local_addr().ok()?.port(), &token).ok()?;
+    let (sender, receiver) = mpsc::channel();
+
+    let expected = token.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            if !authenticate(&mut stream, &expected) {
+                continue;
+            };
+            let Ok(message) = read_message(&mut stream) else {
+                continue;
+            };
+            if stream.write_all(ACK).is_err() {
+                continue;
+            };
+            if sender.send(message).is_err() {
+                // the window is closed
+                break;
+            };
+            ctx.request_repaint();
+        }
+    });
+    Some(Incoming {
+        messages: receiver,
+        token,
+    })
+}
+
+fn array_npy<T: WritableElement>(
+    size: ImageSize,
+    channels: usize,
+    data: &[T],
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut npy = Vec::new();
+    // single-channel images go back as plain HxW arrays
+    if channels == 1 {
+        ArrayView2::from_shape((size.height, size.width), data)?.write_npy(&mut npy)?;
+    } else {
+        ArrayView3::from_shape((size.height, size.width, channels), data)?.write_npy(&mut npy)?;
+    };
+    Ok(npy)
+}
+
+fn channels_npy<T: Pixel + WritableElement>(
+    img: &ChannelsKind<T>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    array_npy(img.size(), img.num_channels(), img.as_slice())
+}
+
+/// Converts widened values back to the dtype they were loaded with, saturating.
+fn widened_npy<T: WritableElement>(
+    img: &ChannelsKind<f32>,
+    cast: impl Fn(f32) -> T,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let data: Vec<T> = img.as_slice().iter().map(|&v| cast(v)).collect();
+    array_npy(img.size(), img.num_channels(), &data)
+}
+
+/// Serializes the image in the dtype of the array it was loaded from.
+fn image_npy(image: &ImageKind) -> Result<Vec<u8>, anyhow::Error> {
+    match image {
+        ImageKind::U8(img) => channels_npy(img),
+        ImageKind::U16(img) => channels_npy(img),
+        ImageKind::F32(img, dtype) => match dtype {
+            DType::Bool => widened_npy(img, |v| v != 0.),
+            DType::U8 => widened_npy(img, |v| v as u8),
+            DType::I8 => widened_npy(img, |v| v as i8),
+            DType::U16 => widened_npy(img, |v| v as u16),
+            DType::I16 => widened_npy(img, |v| v as i16),
+            DType::U32 => widened_npy(img, |v| v as u32),
+            DType::I32 => widened_npy(img, |v| v as i32),
+            DType::I64 => widened_npy(img, |v| v as i64),
+            DType::F32 => channels_npy(img),
+            DType::F64 => widened_npy(img, |v| v as f64),
+        },
+    }
+}
+
+/// Sends every open panel back to Python, with its threshold applied.
+/// Derived panels also name their parent, by title and by index among the panels sent
+/// if it's still open, and the operation that made them.
+pub fn write_results<W: Write>(
+    writer: &mut W,
+    imspections: &[SingleImspection],
+) -> Result<(), anyhow::Error> {
+    let open: Vec<&SingleImspection> = imspections
+        .iter()
+        .filter(|imsp| !imsp.remove_flag)
+        .collect();
+    let mut frames = Vec::with_capacity(open.len());
+    for imspection in &open {
+        let mut params = Param::Bool(imspection.derived.is_some()).line("derived");
+        if let Some(derivation) = &imspection.derived {
+            let parent = imspections
+                .iter()
+                .find(|imsp| imsp.id == derivation.parent_id);
+            if let Some(parent) = parent {
+                params += &Param::Text(parent.title.clone()).line("parent");
+            };
+            if let Some(index) = open.iter().position(|imsp| imsp.id == derivation.parent_id) {
+                params += &Param::Int(index as i64).line("parent_index");
+            };
+            for (key, value) in derivation.operation.params() {
+                params += &value.line(key);
+            }
+        };
+        let image = match imspection.apply_threshold() {
+            Some(thr_image) => {
+                params += &Param::Text(imspection.thr.kind.to_string()).line("threshold");
+                params += &Param::Float(imspection.thr.value).line("threshold_value");
+                thr_image
+            }
+            None => imspection.image.clone(),
+        };
+        frames.push(Frame {
+            title: imspection.title.clone(),
+            params,
+            npy: image_npy(&image)?,
+        });
+    }
+    write_frames(writer, 0, &frames)?;
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use std::str::FromStr;
+
+use ndarray::{Array3, ArrayViewD, Axis, Ix3};
+use pyo3::exceptions::PyValueError;
+use pyo3::PyResult;
+
+/// Axis order of an array passed from Python.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    Hwc,
+    Chw,
+    /// A batch of HWC images.
+    Nhwc,
+    /// A batch of CHW images, as produced by PyTorch models.
+    Nchw,
+    /// A stack of single-channel images, e.g. masks.
+    Nhw,
+    /// A stack of single-channel images along the last axis.
+    Hwn,
+}
+
+/// Images have at most this many channels, which tells channel axes from spatial ones.
+const MAX_CHANNELS: usize = 4;
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "HWC" => Ok(Layout::Hwc),
+            "CHW" => Ok(Layout::Chw),
+            "NHWC" => Ok(Layout::Nhwc),
+            "NCHW" => Ok(Layout::Nchw),
+            "NHW" => Ok(Layout::Nhw),
+            "HWN" => Ok(Layout::Hwn),
+            _ => Err(format!(
+                "Unknown layout '{}', expected one of: HWC, CHW, NHWC, NCHW, NHW, HWN",
+                s
+            )),
+        }
+    }
+}
+
+impl Layout {
+    fn ndim(&self) -> usize {
+        match self {
+            Layout::Hwc | Layout::Chw | Layout::Nhw | Layout::Hwn => 3,
+            Layout::Nhwc | Layout::Nchw => 4,
+        }
+    }
+
+    /// Guesses the layout of a 3-D or 4-D array, preferring channels last.
+    /// 3-D arrays without a channel axis are stacks along the shorter of the first
+    /// and last axes, the way the CLI slices `.npy` volumes.
+    pub fn detect(shape: &[usize]) -> Option<Layout> {
+        let is_channels = |dim: usize| (1..=MAX_CHANNELS).contains(&dim);
+        match shape {
+            [_, _, c] if is_channels(*c) => Some(Layout::Hwc),
+            [c, _, _] if is_channels(*c) => Some(Layout::Chw),
+            [n, _, d] if n <= d => Some(Layout::Nhw),
+            [_, _, _] => Some(Layout::Hwn),
+            [_, _, _, c] if is_channels(*c) => Some(Layout::Nhwc),
+            [_, c, _, _] if is_channels(*c) => Some(Layout::Nchw),
+            _ => None,
+        }
+    }
+}
+
+/// Splits an array into HWC images, one per batch or stack item.
+/// 2-D arrays are single-channel images whatever the `layout`.
+pub fn split_images<T: Clone>(
+    arr: ArrayViewD<T>,
+    layout: Option<Layout>,
+) -> PyResult<Vec<Array3<T>>> {
+    if arr.ndim() == 2 {
+        return Ok(vec![to_hwc(arr.insert_axis(Axis(2)))?]);
+    };
+    let layout = match layout {
+        Some(layout) if layout.ndim() == arr.ndim() => layout,
+        Some(layout) => {
+            return Err(PyValueError::new_err(format!(
+                "Layout {:?} doesn't match an array with {} dimensions",
+                layout,
+                arr.ndim()
+            )))
+        }
+        None => Layout::detect(arr.shape()).ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "Can't detect the layout of an array shaped {:?}, pass `layout`",
+                arr.shape()
+            ))
+        })?,
+    };
+
+    match layout {
+        Layout::Hwc => Ok(vec![to_hwc(arr)?]),
+        Layout::Chw => Ok(vec![to_hwc(arr.permuted_axes(vec![1, 2, 0]))?]),
+        Layout::Nhwc => arr.axis_iter(Axis(0)).map(to_hwc).collect(),
+        Layout::Nchw => arr
+            .axis_iter(Axis(0))
+            .map(|img| to_hwc(img.permuted_axes(vec![1, 2, 0])))
+            .collect(),
+        Layout::Nhw => arr
+            .axis_iter(Axis(0))
+            .map(|img| to_hwc(img.insert_axis(Axis(2))))
+            .collect(),
+        Layout::Hwn => arr
+            .axis_iter(Axis(2))
+            .map(|img| to_hwc(img.insert_axis(Axis(2))))
+            .collect(),
+    }
+}
+
+/// Copies the view into a row-major array, the order images are stored in.
+fn to_hwc<T: Clone>(arr: ArrayViewD<T>) -> PyResult<Array3<T>> {
+    let arr = arr
+        .into_dimensionality::<Ix3>()
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    if arr.dim().2 > MAX_CHANNELS {
+        return Err(PyValueError::new_err(format!(
+            "Images can have at most {} channels, got {}",
+            MAX_CHANNELS,
+            arr.dim().2
+        )));
+    };
+    Ok(arr.as_standard_layout().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{ArrayD, IxDyn};
+
+    /// Array of the given shape whose values count up in row-major order.
+    fn counting(shape: &[usize]) -> ArrayD<u32> {
+        let len = shape.iter().product::<usize>() as u32;
+        ArrayD::from_shape_vec(IxDyn(shape), (0..len).collect()).unwrap()
+    }
+
+    #[test]
+    fn detect_prefers_channels_last() {
+        assert_eq!(Layout::detect(&[10, 20, 3]), Some(Layout::Hwc));
+        assert_eq!(Layout::detect(&[3, 20, 1]), Some(Layout::Hwc));
+        assert_eq!(Layout::detect(&[3, 3, 3]), Some(Layout::Hwc));
+        assert_eq!(Layout::detect(&[3, 20, 10, 4]), Some(Layout::Nhwc));
+    }
+
+    #[test]
+    fn detect_channels_first() {
+        assert_eq!(Layout::detect(&[3, 20, 10]), Some(Layout::Chw));
+        assert_eq!(Layout::detect(&[4, 20, 10]), Some(Layout::Chw));
+        assert_eq!(Layout::detect(&[8, 3, 20, 10]), Some(Layout::Nchw));
+    }
+
+    #[test]
+    fn detect_stacks_along_the_shorter_axis() {
+        assert_eq!(Layout::detect(&[5, 20, 10]), Some(Layout::Nhw));
+        assert_eq!(Layout::detect(&[10, 20, 10]), Some(Layout::Nhw));
+        assert_eq!(Layout::detect(&[10, 20, 5]), Some(Layout::Hwn));
+    }
+
+    #[test]
+    fn detect_rejects_other_shapes() {
+        assert_eq!(Layout::detect(&[10]), None);
+        assert_eq!(Layout::detect(&[10, 20]), None);
+        assert_eq!(Layout::detect(&[8, 10, 20, 5]), None);
+        assert_eq!(Layout::detect(&[2, 8, 10, 20, 3]), None);
+    }
+
+    #[test]
+    fn parses_names_in_any_case() {
+        assert_eq!("nchw".parse(), Ok(Layout::Nchw));
+        assert_eq!("Hwn".parse(), Ok(Layout::Hwn));
+        assert!("WHC".parse::<Layout>().is_err());
+    }
+
+    #[test]
+    fn splits_hw_as_one_channel() {
+        let arr = counting(&[2, 3]);
+        let images = split_images(arr.view(), Some(Layout::Nchw)).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].dim(), (2, 3, 1));
+        assert_eq!(images[0].as_slice().unwrap(), &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn splits_hwc_unchanged() {
+        let arr = counting(&[2, 2, 3]);
+        let images = split_images(arr.view(), None).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].as_slice().unwrap(), arr.as_slice().unwrap());
+    }
+
+    #[test]
+    fn splits_chw_into_interleaved_pixels() {
+        // two channels of 1x2 pixels: [[0, 1]], [[2, 3]]
+        let arr = counting(&[2, 1, 2]);
+        let images = split_images(arr.view(), Some(Layout::Chw)).unwrap();
+        assert_eq!(images[0].dim(), (1, 2, 2));
+        assert_eq!(images[0].as_slice().unwrap(), &[0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn splits_batches() {
+        let arr = counting(&[2, 1, 2, 3]);
+        let images = split_images(arr.view(), None).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[1].dim(), (1, 2, 3));
+        assert_eq!(images[1].as_slice().unwrap(), &[6, 7, 8, 9, 10, 11]);
+
+        // the channel axis of NCHW is second, unless the last axis could be one too
+        let arr = counting(&[2, 3, 1, 5]);
+        let images = split_images(arr.view(), None).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[1].dim(), (1, 5, 3));
+        assert_eq!(images[1].as_slice().unwrap()[..6], [15, 20, 25, 16, 21, 26]);
+        let arr = counting(&[2, 3, 1, 2]);
+        assert_eq!(split_images(arr.view(), None).unwrap()[0].dim(), (3, 1, 2));
+    }
+
+    #[test]
+    fn splits_stacks_of_single_channel_images() {
+        let arr = counting(&[5, 2, 6]);
+        let images = split_images(arr.view(), None).unwrap();
+        assert_eq!(images.len(), 5);
+        assert_eq!(images[1].dim(), (2, 6, 1));
+        assert_eq!(images[1].as_slice().unwrap()[..3], [12, 13, 14]);
+
+        let arr = counting(&[6, 2, 5]);
+        let images = split_images(arr.view(), None).unwrap();
+        assert_eq!(images.len(), 5);
+        assert_eq!(images[1].dim(), (6, 2, 1));
+        assert_eq!(images[1].as_slice().unwrap()[..3], [1, 6, 11]);
+    }
+
+    #[test]
+    fn split_rejects_unknown_shapes() {
+        assert!(split_images(counting(&[6]).view(), None).is_err());
+        assert!(split_images(counting(&[1, 2, 2, 2, 3]).view(), None).is_err());
+        assert!(split_images(counting(&[8, 10, 20, 5]).view(), None).is_err());
+        assert!(split_images(counting(&[2, 2, 3]).view(), Some(Layout::Nhwc)).is_err());
+        assert!(split_images(counting(&[2, 2, 6]).view(), Some(Layout::Hwc)).is_err());
+    }
+}
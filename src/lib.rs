@@ -1,115 +1,312 @@
 use std::env;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, ChildStdout, Command, Stdio};
 use std::thread;
-use std::time::Duration;
 
-use ndarray::{Array3, Axis, Ix3};
-use ndarray_npy::write_npy;
-use numpy::{PyReadonlyArrayDyn, PyUntypedArrayMethods};
+use ndarray::{ArrayD, ArrayViewD};
+use ndarray_npy::{ReadNpyExt, ReadableElement, WritableElement, WriteNpyExt};
+use numpy::{IntoPyArray, PyReadonlyArrayDyn};
 use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::PyTuple;
-use temp_dir::TempDir;
+use pyo3::types::{PyDict, PyList, PyTuple};
 
-use imspect_app::run::imspect_kornia_images;
-use input::load_images;
+use ipc::{
+    find_viewer, read_frames, set_timeouts, write_frames, Frame, ACK, CONNECT_TIMEOUT, FLAG_BLOCK,
+    FLAG_REPLACE,
+};
+use layout::{split_images, Layout};
 
+mod cli;
+mod format;
 mod imspect_app;
 mod input;
+mod ipc;
+mod layout;
+mod npz;
+mod paged;
+mod paths;
+mod raw;
+mod yuv;
+
+/// Hidden option of the CLI that makes the viewer read its images from stdin.
+const IPC_FLAG: &str = "--ipc";
+
+fn npy_frames<T: WritableElement + Clone>(
+    arr: ArrayViewD<T>,
+    layout: Option<Layout>,
+) -> PyResult<Vec<Vec<u8>>> {
+    split_images(arr, layout)?
+        .iter()
+        .map(|img| {
+            let mut frame = Vec::new();
+            img.write_npy(&mut frame).map_err(|_| {
+                PyValueError::new_err(
+                    "Can't pass images to the separate process. Serialization failed",
+                )
+            })?;
+            Ok(frame)
+        })
+        .collect()
+}
+
+/// Serializes every image of the array keeping its dtype, the viewer converts it for display.
+fn array_frames(img: &Bound<'_, PyAny>, layout: Option<Layout>) -> PyResult<Vec<Vec<u8>>> {
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<u8>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<u16>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<f32>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<f64>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<bool>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<i8>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<i16>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<i32>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<u32>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    if let Ok(arr) = img.extract::<PyReadonlyArrayDyn<i64>>() {
+        return npy_frames(arr.as_array(), layout);
+    };
+    Err(PyTypeError::new_err(
+        "Only 'numpy' arrays of bool, integer or float dtype can be accepted",
+    ))
+}
 
 /// to run in a python shell
+///
+/// Positional images are titled by `titles`, keyword images by their keyword.
+/// Arrays are HWC, CHW, batches of them (NHWC, NCHW) or stacks of single-channel
+/// images (NHW, HWN), as given by `layout` or detected from the shape.
+/// Every image of a batch gets its own panel.
+/// Images are added to the window opened by a previous call, if there is one.
+/// `replace` closes its current images first, `new_window` always opens a new window.
+///
+/// With `block` a new window is opened and the call returns once it's closed,
+/// with a dict per panel left open: its `title`, `image` (threshold applied), whether it was
+/// `derived` from another panel, and the `threshold` and `threshold_value` if one was set.
+/// Images keep the dtype they were passed with. The viewer holds 32- and 64-bit integers
+/// as `float32`, so values beyond `2^24` come back rounded.
+/// Derived panels also have their `parent` title, its `parent_index` in the list if it's
+/// still open, the `operation` (`threshold`, `channel`, `color`, `plane`, `demosaic` or
+/// `colorize_cfa`) and its arguments: `operation_threshold` and `operation_threshold_value`,
+/// `channel`, `color`, `plane`, `cfa_pattern` and `demosaic`.
 #[pyfunction]
-#[pyo3(name = "imspect", signature = (* imgs))]
-fn _imspect_for_shell<'py>(_py: Python<'py>, imgs: &Bound<'py, PyTuple>) -> PyResult<()> {
-    if imgs.is_empty() {
-        println!("Provide at least one 'numpy' image");
-        return Ok(());
-    };
-    let mut imgs_vec: Vec<Array3<u8>> = Vec::with_capacity(imgs.len());
-
-    for img in imgs.iter() {
-        let py_arr: PyReadonlyArrayDyn<'py, u8> = img
-            .extract()
-            .map_err(|_| PyTypeError::new_err("Only 'uint8' array images can be accepted"))?;
-        let rs_arr: Array3<u8> = match py_arr.ndim() {
-            0..2 => {
-                return Err(PyTypeError::new_err(
-                    "Only array images with 2 or 3 dimensions can be accepted",
-                ));
-            }
-            3 => py_arr
-                .as_array()
-                .into_dimensionality::<Ix3>()
-                .unwrap()
-                .to_owned(),
-            2 => py_arr
-                .as_array()
-                .insert_axis(Axis(2))
-                .into_dimensionality::<Ix3>()
-                .unwrap()
-                .to_owned(),
-            4.. => {
-                return Err(PyTypeError::new_err(
-                    "Only array images with 2 or 3 dimensions can be accepted",
-                ));
-            }
-        };
-        imgs_vec.push(rs_arr);
+#[pyo3(
+    name = "imspect",
+    signature = (* imgs, titles = None, layout = None, replace = false, new_window = false, block = false, ** named)
+)]
+#[allow(clippy::too_many_arguments)]
+fn _imspect_for_shell<'py>(
+    py: Python<'py>,
+    imgs: &Bound<'py, PyTuple>,
+    titles: Option<Vec<String>>,
+    layout: Option<&str>,
+    replace: bool,
+    new_window: bool,
+    block: bool,
+    named: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Option<Bound<'py, PyList>>> {
+    let layout: Option<Layout> = layout
+        .map(str::parse)
+        .transpose()
+        .map_err(PyValueError::new_err)?;
+    let titles = titles.unwrap_or_default();
+    let mut named_imgs: Vec<(String, Bound<'py, PyAny>)> = Vec::with_capacity(imgs.len());
+
+    for (i, img) in imgs.iter().enumerate() {
+        let title = titles
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| format!("image {}", i));
+        named_imgs.push((title, img));
     }
-    let mut img_paths: Vec<PathBuf> = Vec::with_capacity(imgs_vec.len());
-    let tmp_dir = TempDir::new().map_err(|_| {
-        PyValueError::new_err(
-            "Can't pass images to the separate process. Temp dir is not accessible",
-        )
-    })?;
-    for (i, img) in imgs_vec.iter().enumerate() {
-        let f = tmp_dir.child(format!("imspect_img_{}.npy", i));
-        write_npy(&f, img).map_err(|_| {
-            PyValueError::new_err(
-                "Can't pass images to the separate process. Temp dir is not accessible",
-            )
-        })?;
+    if let Some(named) = named {
+        for (key, img) in named.iter() {
+            named_imgs.push((key.extract()?, img));
+        }
+    };
 
-        img_paths.push(f);
+    let mut frames: Vec<Frame> = Vec::with_capacity(named_imgs.len());
+    for (title, img) in named_imgs {
+        let img_frames = array_frames(&img, layout)?;
+        let batched = img_frames.len() > 1;
+        frames.extend(img_frames.into_iter().enumerate().map(|(n, npy)| Frame {
+            title: if batched {
+                format!("{}[{}]", title, n)
+            } else {
+                title.clone()
+            },
+            params: String::new(),
+            npy,
+        }));
     }
+    if frames.is_empty() {
+        println!("Provide at least one 'numpy' image");
+        return Ok(None);
+    };
 
+    let mut flags = if replace { FLAG_REPLACE } else { 0 };
+    if block {
+        flags |= FLAG_BLOCK;
+    };
+
+    if !new_window && !block {
+        if let Some(mut stream) = connect_viewer() {
+            return send_message(&mut stream, flags, &frames)
+                .map(|_| None)
+                .map_err(|_| {
+                    PyRuntimeError::new_err(
+                        "Can't pass images to the running viewer. It didn't confirm receiving them",
+                    )
+                });
+        };
+    };
+
+    // no viewer is running, start one that will listen for the next calls
     let path = "imspect";
-    let status = match Command::new("imspect")
-        .args(&img_paths)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
+    let mut child = Command::new(path)
+        .arg(IPC_FLAG)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
         .stderr(Stdio::null())
         .spawn()
-    {
-        Err(_) => Err(PyRuntimeError::new_err(
-            format!("Can't find '{}' script to run", path),
-        )),
-        Ok(_) => Ok(()),
+        .map_err(|_| PyRuntimeError::new_err(format!("Can't find '{}' script to run", path)))?;
+
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let handoff = send_to_child(&mut child, &mut stdout, flags, &frames);
+    if handoff.is_err() || !block {
+        // reap the viewer when it's closed, so it doesn't linger as a zombie
+        thread::spawn(move || child.wait());
+        return handoff.map(|_| None).map_err(|_| {
+            PyRuntimeError::new_err(
+                "Can't pass images to the separate process. It didn't confirm receiving them",
+            )
+        });
     };
 
-    thread::sleep(Duration::from_millis(500)); // give time to read images
+    // let other Python threads run while the window is open
+    let results = py.allow_threads(|| {
+        let results = read_frames(&mut stdout);
+        let _ = child.wait();
+        results
+    });
+    let (_, frames) = results.map_err(|_| {
+        PyRuntimeError::new_err("The viewer closed without sending the images back")
+    })?;
+    results_to_py(py, frames).map(Some)
+}
 
-    status
+fn results_to_py(py: Python<'_>, frames: Vec<Frame>) -> PyResult<Bound<'_, PyList>> {
+    let results = PyList::empty_bound(py);
+    for frame in frames {
+        let result = PyDict::new_bound(py);
+        result.set_item("title", frame.title)?;
+        result.set_item("image", npy_to_py(py, &frame.npy)?)?;
+        for line in frame.params.lines() {
+            if let Some((key, value)) = param_to_py(py, line) {
+                result.set_item(key, value)?;
+            };
+        }
+        results.append(result)?;
+    }
+    Ok(results)
 }
 
-/// to run in a command prompt
-#[pyfunction]
-fn _imspect_script() -> PyResult<()> {
-    let args: Vec<PathBuf> = env::args().skip(2).map(PathBuf::from).collect();
+fn read_npy_to_py<T: ReadableElement + numpy::Element>(
+    py: Python<'_>,
+    npy: &[u8],
+) -> Option<PyObject> {
+    let arr = ArrayD::<T>::read_npy(npy).ok()?;
+    Some(arr.into_pyarray_bound(py).into_any().unbind())
+}
+
+/// The viewer sends images back in the dtype they were sent with.
+fn npy_to_py(py: Python<'_>, npy: &[u8]) -> PyResult<PyObject> {
+    read_npy_to_py::<u8>(py, npy)
+        .or_else(|| read_npy_to_py::<u16>(py, npy))
+        .or_else(|| read_npy_to_py::<f32>(py, npy))
+        .or_else(|| read_npy_to_py::<f64>(py, npy))
+        .or_else(|| read_npy_to_py::<bool>(py, npy))
+        .or_else(|| read_npy_to_py::<i8>(py, npy))
+        .or_else(|| read_npy_to_py::<i16>(py, npy))
+        .or_else(|| read_npy_to_py::<i32>(py, npy))
+        .or_else(|| read_npy_to_py::<u32>(py, npy))
+        .or_else(|| read_npy_to_py::<i64>(py, npy))
+        .ok_or_else(|| PyValueError::new_err("Can't read an image sent back by the viewer"))
+}
 
-    if args.is_empty() {
-        println!("Provide at least one image path");
-        return Ok(());
+/// Key and value of a `key:type=value` line written by [`ipc::Param::line`].
+fn param_to_py<'a>(py: Python<'_>, line: &'a str) -> Option<(&'a str, PyObject)> {
+    let (key, value) = line.split_once('=')?;
+    let (key, kind) = key.split_once(':')?;
+    let value = match kind {
+        "bool" => value.parse::<bool>().ok()?.into_py(py),
+        "int" => value.parse::<i64>().ok()?.into_py(py),
+        "float" => value.parse::<f64>().ok()?.into_py(py),
+        _ => value.into_py(py),
     };
+    Some((key, value))
+}
 
-    match load_images(args) {
-        Ok(imgs) => match imspect_kornia_images(imgs) {
-            Ok(_) => Ok(()),
-            Err(err) => Err(PyRuntimeError::new_err(err.to_string())),
-        },
-        Err(err) => Err(PyRuntimeError::new_err(err.to_string())),
-    }
+/// Connects to the running viewer and sends its token, `None` if no viewer is running.
+fn connect_viewer() -> Option<TcpStream> {
+    let (addr, token) = find_viewer()?;
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).ok()?;
+    set_timeouts(&stream).ok()?;
+    stream.write_all(token.as_bytes()).ok()?;
+    Some(stream)
+}
+
+/// Writes a message, see [`ipc`], to a running viewer and waits for the acknowledgment.
+fn send_message<S: Read + Write>(stream: &mut S, flags: u8, frames: &[Frame]) -> io::Result<()> {
+    write_frames(stream, flags, frames)?;
+    read_ack(stream)
+}
+
+fn send_to_child(
+    child: &mut Child,
+    stdout: &mut ChildStdout,
+    flags: u8,
+    frames: &[Frame],
+) -> io::Result<()> {
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    write_frames(&mut stdin, flags, frames)?;
+    drop(stdin);
+
+    read_ack(stdout)
+}
+
+fn read_ack<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut ack = vec![0; ACK.len()];
+    reader.read_exact(&mut ack)?;
+    if ack != ACK {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unexpected answer",
+        ));
+    };
+    Ok(())
+}
+
+/// to run in a command prompt, the returned exit code is passed to `sys.exit`
+#[pyfunction]
+fn _imspect_script() -> u8 {
+    cli::run(env::args_os().skip(2).collect())
 }
 
 /// A Python module implemented in Rust.
@@ -1,23 +1,18 @@
 use std::env;
-use std::path::PathBuf;
-
-use imspect_app::run::imspect_kornia_images;
-use input::load_images;
+use std::process::ExitCode;
 
+mod cli;
+mod format;
 mod input;
 
 mod imspect_app;
+mod ipc;
+mod npz;
+mod paged;
+mod paths;
+mod raw;
+mod yuv;
 
-fn main() -> eframe::Result<()> {
-    let args: Vec<PathBuf> = env::args().skip(1).map(PathBuf::from).collect();
-
-    if args.is_empty() {
-        println!("Provide at least one image path");
-        return Ok(());
-    };
-
-    if let Ok(imgs) = load_images(args) {
-        return imspect_kornia_images(imgs);
-    };
-    Ok(())
+fn main() -> ExitCode {
+    ExitCode::from(cli::run(env::args_os().skip(1).collect()))
 }
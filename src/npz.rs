@@ -0,0 +1,92 @@
+//! `np.savez` archives, every array is addressed as `bundle.npz/KEY`.
+
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use zip::ZipArchive;
+
+/// Splits `bundle.npz/masks/a` into the archive and the key of the array, `masks/a`.
+/// Keys may hold `/`, so the archive is the nearest `.npz` ancestor that is a file.
+pub fn split_member(path: &Path) -> Option<(&Path, String)> {
+    let archive = path.ancestors().skip(1).find(|archive| {
+        archive
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("npz"))
+            && archive.is_file()
+    })?;
+    let key = path
+        .strip_prefix(archive)
+        .ok()?
+        .iter()
+        .map(|part| part.to_str())
+        .collect::<Option<Vec<_>>>()?
+        .join("/");
+    Some((archive, key))
+}
+
+fn open(bytes: &[u8]) -> Result<ZipArchive<Cursor<&[u8]>>, anyhow::Error> {
+    ZipArchive::new(Cursor::new(bytes)).context("Failed to read the archive")
+}
+
+/// Keys of the arrays in the order they were saved, without the `.npy` of the file names.
+fn archive_keys<R: Read + Seek>(archive: &ZipArchive<R>) -> Vec<String> {
+    (0..archive.len())
+        .filter_map(|i| archive.name_for_index(i)?.strip_suffix(".npy"))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Keys of the arrays in the archive held in `bytes`, see [`archive_keys`].
+pub fn keys(bytes: &[u8]) -> Result<Vec<String>, anyhow::Error> {
+    Ok(archive_keys(&open(bytes)?))
+}
+
+/// Keys of the arrays in the archive at `path`, reading its directory only.
+pub fn file_keys(path: &Path) -> Result<Vec<String>, anyhow::Error> {
+    let archive = ZipArchive::new(File::open(path)?).context("Failed to read the archive")?;
+    Ok(archive_keys(&archive))
+}
+
+/// The serialized `.npy` array saved under `key`.
+pub fn member(bytes: &[u8], key: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut archive = open(bytes)?;
+    let mut file = archive
+        .by_name(&format!("{}.npy", key))
+        .map_err(|_| anyhow!("The archive has no array '{}'", key))?;
+    let mut npy = Vec::with_capacity(file.size() as usize);
+    file.read_to_end(&mut npy)
+        .with_context(|| format!("Failed to extract '{}'", key))?;
+    Ok(npy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn split_member_stops_at_the_archive() {
+        let dir = std::env::temp_dir().join(format!("imspect-npz-{}.npz", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("bundle.npz");
+        fs::write(&archive, b"").unwrap();
+
+        let member = archive.join("mask");
+        assert_eq!(
+            split_member(&member),
+            Some((archive.as_path(), "mask".into()))
+        );
+        let member = archive.join("masks/a.npz/b");
+        assert_eq!(
+            split_member(&member),
+            Some((archive.as_path(), "masks/a.npz/b".into()))
+        );
+        // the directory is named like an archive, but isn't one
+        assert!(split_member(&archive).is_none());
+        assert!(split_member(&dir.join("other.npy/mask")).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,592 @@
+//! `.npy` images too large to read whole, opened in place so that only the pixels
+//! sampled for display are read from disk. Samples are read through the file handle
+//! rather than a memory map, so a file truncated while it is shown fails the read
+//! instead of bringing the viewer down.
+
+use std::fs::File;
+use std::io;
+
+use anyhow::{anyhow, Context};
+use kornia::image::ImageSize;
+
+use crate::imspect_app::image_kind::{ChannelsKind, ColorSpaceChange, DType, ImageKind};
+
+/// `.npy` files from this size on are opened in place rather than read.
+pub const PAGED_MIN_LEN: u64 = 256 << 20;
+/// Longest side of the preview a paged array opens with.
+pub const PREVIEW_SIDE: usize = 256;
+/// Longest side of the overview made in the background.
+pub const OVERVIEW_SIDE: usize = 2048;
+
+/// A rectangle of the array, sampled every `step` pixels along both axes.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub step: usize,
+}
+
+impl Region {
+    /// Size of the image the samples make.
+    pub fn sampled_size(&self) -> ImageSize {
+        ImageSize {
+            width: self.width.div_ceil(self.step),
+            height: self.height.div_ceil(self.step),
+        }
+    }
+
+    pub fn contains(&self, other: &Region) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && other.x + other.width <= self.x + self.width
+            && other.y + other.height <= self.y + self.height
+    }
+}
+
+/// Longer headers aren't written by numpy, the file is left to the whole read.
+const MAX_HEADER_LEN: usize = 1 << 16;
+/// Reading a row at once is cheaper than reading its samples apart, until they are this far apart.
+const SPAN_READ_MAX_GAP: usize = 4096;
+
+/// Sample type of a `.npy` type descriptor, e.g. `<u2`, if it can be read in place:
+/// `bool` and foreign byte orders are left out, they need converting.
+fn parse_descr(descr: &str) -> Option<DType> {
+    let native = if cfg!(target_endian = "little") {
+        '<'
+    } else {
+        '>'
+    };
+    let mut chars = descr.chars();
+    let order = chars.next()?;
+    if order != native && order != '|' && order != '=' {
+        return None;
+    };
+    let dtype = match chars.as_str() {
+        "u1" => DType::U8,
+        "i1" => DType::I8,
+        "u2" => DType::U16,
+        "i2" => DType::I16,
+        "u4" => DType::U32,
+        "i4" => DType::I32,
+        "i8" => DType::I64,
+        "f4" => DType::F32,
+        "f8" => DType::F64,
+        _ => return None,
+    };
+    Some(dtype)
+}
+
+/// What follows `'key':` in the header dict.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let at = header.find(&format!("'{}'", key))?;
+    let rest = header[at + key.len() + 2..].trim_start();
+    Some(rest.strip_prefix(':')?.trim_start())
+}
+
+/// Sample type, Fortran order and shape from the header dict of a `.npy` file.
+fn parse_header(header: &str) -> Option<(DType, bool, Vec<usize>)> {
+    let descr = header_value(header, "descr")?;
+    let quote = descr.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let descr = descr[1..].split(quote).next()?;
+    let fortran_order = header_value(header, "fortran_order")?.starts_with("True");
+    let shape = header_value(header, "shape")?.strip_prefix('(')?;
+    let shape = shape[..shape.find(')')?]
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().ok())
+        .collect::<Option<_>>()?;
+    Some((parse_descr(descr)?, fortran_order, shape))
+}
+
+/// Size in bytes of a sample of `dtype`.
+fn item_size(dtype: DType) -> usize {
+    match dtype {
+        DType::Bool | DType::U8 | DType::I8 => 1,
+        DType::U16 | DType::I16 => 2,
+        DType::U32 | DType::I32 | DType::F32 => 4,
+        DType::I64 | DType::F64 => 8,
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(())
+}
+
+/// Converts every `N` bytes in the byte order of the machine to a sample.
+fn from_ne<T, const N: usize>(bytes: &[u8], from_ne_bytes: fn([u8; N]) -> T) -> Vec<T> {
+    bytes
+        .chunks_exact(N)
+        .map(|chunk| from_ne_bytes(chunk.try_into().expect("chunks have N bytes")))
+        .collect()
+}
+
+pub struct PagedNpy {
+    file: File,
+    dtype: DType,
+    /// Byte offset of the first sample in the file.
+    data_offset: u64,
+    /// Samples from one row, column and channel to the next.
+    strides: [usize; 3],
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    /// Swap the red and blue channels of what is read, see [`crate::input::bgr_to_rgb`].
+    pub bgr: bool,
+}
+
+impl PagedNpy {
+    /// Opens the file in place if it is a `HxW` or `HxWxC` array that can be read as stored,
+    /// `None` for other files, volumes, `bool` arrays and byte orders that need converting.
+    pub fn open(file: &File) -> Result<Option<Self>, anyhow::Error> {
+        let mut prefix = [0; 12];
+        if read_at(file, &mut prefix, 0).is_err() || !prefix.starts_with(b"\x93NUMPY") {
+            return Ok(None);
+        };
+        let (header_len, header_start) = match prefix[6] {
+            1 => (u16::from_le_bytes([prefix[8], prefix[9]]) as usize, 10),
+            _ => (
+                u32::from_le_bytes([prefix[8], prefix[9], prefix[10], prefix[11]]) as usize,
+                12,
+            ),
+        };
+        let mut header = vec![0; header_len.min(MAX_HEADER_LEN)];
+        if header_len > MAX_HEADER_LEN || read_at(file, &mut header, header_start as u64).is_err() {
+            return Ok(None);
+        };
+        let Some((dtype, fortran_order, shape)) = parse_header(&String::from_utf8_lossy(&header))
+        else {
+            return Ok(None);
+        };
+        let (height, width, channels) = match shape[..] {
+            [height, width] => (height, width, 1),
+            [height, width, channels] if (1..=4).contains(&channels) => (height, width, channels),
+            _ => return Ok(None),
+        };
+        if width == 0 || height == 0 {
+            return Ok(None);
+        };
+        let data_offset = (header_start + header_len) as u64;
+        let data_len = [width, channels, item_size(dtype)]
+            .into_iter()
+            .try_fold(height as u64, |len, dim| len.checked_mul(dim as u64));
+        if data_len.and_then(|len| len.checked_add(data_offset)) != Some(file.metadata()?.len()) {
+            // let the whole read report it
+            return Ok(None);
+        };
+        let strides = if fortran_order {
+            [1, height, height * width]
+        } else {
+            [width * channels, channels, 1]
+        };
+        Ok(Some(Self {
+            file: file.try_clone()?,
+            dtype,
+            data_offset,
+            strides,
+            width,
+            height,
+            channels,
+            bgr: false,
+        }))
+    }
+
+    /// Step that samples the whole array to at most `max_side` pixels along its longest side.
+    pub fn step_for(&self, max_side: usize) -> usize {
+        self.width.max(self.height).div_ceil(max_side).max(1)
+    }
+
+    /// The whole array sampled to at most `max_side` pixels along its longest side.
+    pub fn overview(&self, max_side: usize) -> Result<ImageKind, anyhow::Error> {
+        let step = self.step_for(max_side);
+        let region = Region {
+            x: 0,
+            y: 0,
+            width: self.width,
+            height: self.height,
+            step,
+        };
+        self.region(region)
+    }
+
+    /// The pixel at column `x` and row `y` as a 1x1 image.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<ImageKind> {
+        if x >= self.width || y >= self.height {
+            return None;
+        };
+        let region = Region {
+            x,
+            y,
+            width: 1,
+            height: 1,
+            step: 1,
+        };
+        self.region(region).ok()
+    }
+
+    /// Bytes of the samples of `region`, pixel by pixel.
+    fn region_bytes(&self, region: Region) -> io::Result<Vec<u8>> {
+        let item_size = item_size(self.dtype);
+        let pixel_len = self.channels * item_size;
+        let size = region.sampled_size();
+        let [row_stride, column_stride, channel_stride] = self.strides;
+        let offset = |y: usize, x: usize, c: usize| {
+            let samples = (y * row_stride + x * column_stride + c * channel_stride) as u64;
+            self.data_offset + samples * item_size as u64
+        };
+        let packed_pixels = channel_stride == 1 && column_stride == self.channels;
+        let gap = region.step * pixel_len;
+
+        let mut bytes = vec![0; size.width * size.height * pixel_len];
+        let mut pixels = bytes.chunks_exact_mut(pixel_len);
+        for y in (region.y..region.y + region.height).step_by(region.step) {
+            let xs = (region.x..region.x + region.width).step_by(region.step);
+            if packed_pixels && gap <= SPAN_READ_MAX_GAP {
+                let mut span = vec![0; (size.width - 1) * gap + pixel_len];
+                read_at(&self.file, &mut span, offset(y, region.x, 0))?;
+                for (sampled, pixel) in span.chunks(gap).zip(pixels.by_ref()) {
+                    pixel.copy_from_slice(&sampled[..pixel_len]);
+                }
+            } else if packed_pixels {
+                for (x, pixel) in xs.zip(pixels.by_ref()) {
+                    read_at(&self.file, pixel, offset(y, x, 0))?;
+                }
+            } else {
+                for (x, pixel) in xs.zip(pixels.by_ref()) {
+                    for (c, sample) in pixel.chunks_exact_mut(item_size).enumerate() {
+                        read_at(&self.file, sample, offset(y, x, c))?;
+                    }
+                }
+            };
+        }
+        Ok(bytes)
+    }
+
+    /// Reads the samples of `region`, which must lie within the array.
+    pub fn region(&self, region: Region) -> Result<ImageKind, anyhow::Error> {
+        if region.step == 0
+            || region.x + region.width > self.width
+            || region.y + region.height > self.height
+        {
+            return Err(anyhow!(
+                "The region {:?} is outside of the {}x{} array",
+                region,
+                self.width,
+                self.height
+            ));
+        };
+        let size = region.sampled_size();
+        let channels = self.channels;
+        let widened = |values: Vec<f32>| -> Result<ImageKind, anyhow::Error> {
+            Ok(ImageKind::F32(
+                ChannelsKind::new(size, channels, values)?,
+                self.dtype,
+            ))
+        };
+        let bytes = self
+            .region_bytes(region)
+            .context("Failed to read the array, it may have been rewritten")?;
+        let image = match self.dtype {
+            DType::U8 => ImageKind::U8(ChannelsKind::new(size, channels, bytes)?),
+            DType::U16 => ImageKind::U16(ChannelsKind::new(
+                size,
+                channels,
+                from_ne(&bytes, u16::from_ne_bytes),
+            )?),
+            DType::F32 => widened(from_ne(&bytes, f32::from_ne_bytes))?,
+            DType::I8 => widened(bytes.iter().map(|&v| v as i8 as f32).collect())?,
+            DType::I16 => widened(
+                from_ne(&bytes, i16::from_ne_bytes)
+                    .into_iter()
+                    .map(f32::from)
+                    .collect(),
+            )?,
+            DType::U32 => widened(
+                from_ne(&bytes, u32::from_ne_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            )?,
+            DType::I32 => widened(
+                from_ne(&bytes, i32::from_ne_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            )?,
+            DType::I64 => widened(
+                from_ne(&bytes, i64::from_ne_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            )?,
+            DType::F64 => widened(
+                from_ne(&bytes, f64::from_ne_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            )?,
+            DType::Bool => return Err(anyhow!("bool arrays aren't read in place")),
+        };
+        let change = match (self.bgr, channels) {
+            (true, 3) => ColorSpaceChange::BGR2RGB,
+            (true, 4) => ColorSpaceChange::BGRA2RGBA,
+            _ => return Ok(image),
+        };
+        Ok(image.change_color(change)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Byte order marker that is not the one of the machine.
+    const FOREIGN: char = if cfg!(target_endian = "little") {
+        '>'
+    } else {
+        '<'
+    };
+
+    /// Writes a `.npy` file with the given header dict and format `version`, padded like numpy.
+    fn npy_file(name: &str, version: u8, header: &str, data: &[u8]) -> PathBuf {
+        let prefix_len = if version == 1 { 10 } else { 12 };
+        // numpy pads the header so that the data starts at a multiple of 64 bytes
+        let padding = 63 - (prefix_len + header.len()) % 64;
+        let mut header = format!("{}{}", header, " ".repeat(padding));
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY".to_vec();
+        bytes.extend([version, 0]);
+        if version == 1 {
+            bytes.extend((header.len() as u16).to_le_bytes());
+        } else {
+            bytes.extend((header.len() as u32).to_le_bytes());
+        };
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        let path =
+            std::env::temp_dir().join(format!("imspect-paged-{}-{}", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// Native `u16` samples counting up from 0.
+    fn counting_u16(len: usize) -> Vec<u8> {
+        (0..len as u16).flat_map(u16::to_ne_bytes).collect()
+    }
+
+    fn open(path: &PathBuf) -> Option<PagedNpy> {
+        let paged = PagedNpy::open(&File::open(path).unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        paged
+    }
+
+    fn u16_header(fortran_order: bool, shape: &str) -> String {
+        let order = if cfg!(target_endian = "little") {
+            '<'
+        } else {
+            '>'
+        };
+        let fortran_order = if fortran_order { "True" } else { "False" };
+        format!(
+            "{{'descr': '{}u2', 'fortran_order': {}, 'shape': {}, }}",
+            order, fortran_order, shape
+        )
+    }
+
+    fn region_values(paged: &PagedNpy, region: Region) -> Vec<u16> {
+        match paged.region(region).unwrap() {
+            ImageKind::U16(img) => img.as_slice().to_vec(),
+            _ => panic!("expected a 16-bit image"),
+        }
+    }
+
+    fn region(x: usize, y: usize, width: usize, height: usize, step: usize) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+            step,
+        }
+    }
+
+    #[test]
+    fn parses_header_dicts() {
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (3, 4), }";
+        assert_eq!(parse_header(header), Some((DType::U8, false, vec![3, 4])));
+        let header = "{'descr': \"=f8\", 'fortran_order': True, 'shape': (3, 4, 2)}";
+        assert_eq!(
+            parse_header(header),
+            Some((DType::F64, true, vec![3, 4, 2]))
+        );
+        let header = "{'descr': '|u1', 'fortran_order': False, 'shape': (5,), }";
+        assert_eq!(parse_header(header), Some((DType::U8, false, vec![5])));
+    }
+
+    #[test]
+    fn header_rejects_types_that_need_converting() {
+        let foreign = format!(
+            "{{'descr': '{}u2', 'fortran_order': False, 'shape': (3, 4), }}",
+            FOREIGN
+        );
+        assert_eq!(parse_header(&foreign), None);
+        let header = "{'descr': '|b1', 'fortran_order': False, 'shape': (3, 4), }";
+        assert_eq!(parse_header(header), None);
+        let header = "{'descr': '<c8', 'fortran_order': False, 'shape': (3, 4), }";
+        assert_eq!(parse_header(header), None);
+        assert_eq!(parse_header("{'descr': '|u1'}"), None);
+    }
+
+    #[test]
+    fn opens_version_1_and_2_headers() {
+        for version in [1, 2, 3] {
+            let path = npy_file(
+                &format!("v{}", version),
+                version,
+                &u16_header(false, "(3, 5)"),
+                &counting_u16(15),
+            );
+            let paged = open(&path).unwrap();
+            assert_eq!((paged.width, paged.height, paged.channels), (5, 3, 1));
+            assert_eq!(paged.strides, [5, 1, 1]);
+            assert_eq!(
+                region_values(&paged, region(0, 0, 5, 3, 1)),
+                (0..15).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn leaves_other_files_to_the_whole_read() {
+        let data = counting_u16(15);
+        let bad_magic = npy_file("magic", 1, &u16_header(false, "(3, 5)"), &data);
+        let mut bytes = std::fs::read(&bad_magic).unwrap();
+        bytes[1] = b'X';
+        std::fs::write(&bad_magic, bytes).unwrap();
+        assert!(open(&bad_magic).is_none());
+
+        let foreign = format!(
+            "{{'descr': '{}u2', 'fortran_order': False, 'shape': (3, 5), }}",
+            FOREIGN
+        );
+        assert!(open(&npy_file("foreign", 1, &foreign, &data)).is_none());
+        assert!(open(&npy_file("short", 1, &u16_header(false, "(3, 6)"), &data)).is_none());
+        assert!(open(&npy_file(
+            "volume",
+            1,
+            &u16_header(false, "(3, 1, 5)"),
+            &data
+        ))
+        .is_none());
+        assert!(open(&npy_file("flat", 1, &u16_header(false, "(15,)"), &data)).is_none());
+        assert!(open(&npy_file("empty", 1, &u16_header(false, "(0, 5)"), &[])).is_none());
+    }
+
+    #[test]
+    fn reads_regions_up_to_the_array_edges() {
+        // 4 rows of 6 pixels, valued 10 * row + column
+        let data: Vec<u8> = (0..4u16)
+            .flat_map(|y| (0..6u16).map(move |x| 10 * y + x))
+            .flat_map(u16::to_ne_bytes)
+            .collect();
+        let paged = open(&npy_file("edges", 1, &u16_header(false, "(4, 6)"), &data)).unwrap();
+        assert_eq!(region_values(&paged, region(5, 3, 1, 1, 1)), [35]);
+        assert_eq!(
+            region_values(&paged, region(4, 2, 2, 2, 1)),
+            [24, 25, 34, 35]
+        );
+        // the last samples don't have to reach the edge
+        assert_eq!(region_values(&paged, region(1, 0, 5, 4, 3)), [1, 4, 31, 34]);
+        assert_eq!(paged.overview(2).unwrap().width(), 2);
+        assert!(paged.region(region(5, 0, 2, 1, 1)).is_err());
+        assert!(paged.region(region(0, 3, 1, 2, 1)).is_err());
+        assert!(paged.region(region(0, 0, 1, 1, 0)).is_err());
+        assert!(paged.pixel(6, 0).is_none());
+    }
+
+    #[test]
+    fn reads_fortran_order_through_its_strides() {
+        // the 3x2x2 array [y][x][c] = 100 * y + 10 * x + c, stored column-major
+        let mut data = Vec::new();
+        for c in 0..2u16 {
+            for x in 0..2u16 {
+                for y in 0..3u16 {
+                    data.extend((100 * y + 10 * x + c).to_ne_bytes());
+                }
+            }
+        }
+        let path = npy_file("fortran", 1, &u16_header(true, "(3, 2, 2)"), &data);
+        let paged = open(&path).unwrap();
+        assert_eq!(paged.strides, [1, 3, 6]);
+        assert_eq!(
+            region_values(&paged, region(0, 0, 2, 3, 1)),
+            [0, 1, 10, 11, 100, 101, 110, 111, 200, 201, 210, 211]
+        );
+        assert_eq!(
+            region_values(&paged, region(1, 1, 1, 2, 1)),
+            [110, 111, 210, 211]
+        );
+    }
+
+    #[test]
+    fn reads_samples_far_apart_one_by_one() {
+        // a step wider than a span read
+        let width = SPAN_READ_MAX_GAP;
+        let data = counting_u16(2 * width);
+        let shape = format!("(2, {})", width);
+        let paged = open(&npy_file("wide", 1, &u16_header(false, &shape), &data)).unwrap();
+        let step = SPAN_READ_MAX_GAP / 2 + 1;
+        assert_eq!(
+            region_values(&paged, region(0, 0, width, 2, step)),
+            [0, step as u16]
+        );
+        assert_eq!(
+            region_values(&paged, region(1, 1, width - 1, 1, step)),
+            [width as u16 + 1, (width + 1 + step) as u16]
+        );
+    }
+
+    #[test]
+    fn truncated_files_fail_the_read() {
+        let path = npy_file(
+            "truncated",
+            1,
+            &u16_header(false, "(3, 5)"),
+            &counting_u16(15),
+        );
+        let paged = PagedNpy::open(&File::open(&path).unwrap())
+            .unwrap()
+            .unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(80)
+            .unwrap();
+        assert!(paged.region(region(0, 0, 5, 3, 1)).is_err());
+        assert!(paged.pixel(4, 2).is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
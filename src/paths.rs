@@ -0,0 +1,236 @@
+//! Expansion of directories and glob patterns given on the command line.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::input::LoadError;
+
+/// Files picked from directories and patterns when no extensions are given.
+pub const IMAGE_EXTENSIONS: &[&str] = &[
+    "npy", "npz", "png", "jpg", "jpeg", "bmp", "gif", "tif", "tiff", "webp", "pbm", "pgm", "ppm",
+    "pnm",
+];
+
+/// Which files directories and glob patterns expand to.
+pub struct Expansion {
+    pub recursive: bool,
+    /// Lowercase extensions without the dot, [`IMAGE_EXTENSIONS`] if empty.
+    pub extensions: Vec<String>,
+}
+
+impl Expansion {
+    fn accepts(&self, path: &Path) -> bool {
+        let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+            return false;
+        };
+        let extension = extension.to_ascii_lowercase();
+        if self.extensions.is_empty() {
+            IMAGE_EXTENSIONS.contains(&extension.as_str())
+        } else {
+            self.extensions.contains(&extension)
+        }
+    }
+
+    /// Replaces directories and glob patterns by the files they contain, in natural order.
+    /// Explicit file paths are kept as they are, even with other extensions.
+    pub fn expand(&self, args: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<LoadError>) {
+        let mut paths = Vec::with_capacity(args.len());
+        let mut errors = Vec::new();
+
+        for arg in args {
+            let expanded = if arg.is_dir() {
+                self.expand_dir(&arg)
+            } else if !arg.exists() && is_pattern(&arg) {
+                self.expand_pattern(&arg)
+            } else {
+                paths.push(arg);
+                continue;
+            };
+            match expanded {
+                Ok(mut files) if !files.is_empty() => {
+                    files.sort_by(|a, b| natural_cmp(a, b));
+                    paths.extend(files);
+                }
+                Ok(_) => errors.push(LoadError {
+                    path: arg,
+                    error: anyhow!("No images found"),
+                }),
+                Err(error) => errors.push(LoadError { path: arg, error }),
+            }
+        }
+        (paths, errors)
+    }
+
+    fn expand_dir(&self, dir: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if self.recursive {
+                    files.extend(self.expand_dir(&path)?);
+                };
+            } else if self.accepts(&path) {
+                files.push(path);
+            };
+        }
+        Ok(files)
+    }
+
+    fn expand_pattern(&self, pattern: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let pattern = pattern
+            .to_str()
+            .ok_or_else(|| anyhow!("The pattern isn't valid UTF-8"))?;
+        let mut files = Vec::new();
+        for path in glob::glob(pattern)? {
+            let path = path?;
+            if path.is_dir() {
+                if self.recursive {
+                    files.extend(self.expand_dir(&path)?);
+                };
+            } else if self.accepts(&path) {
+                files.push(path);
+            };
+        }
+        Ok(files)
+    }
+}
+
+/// Shells expand patterns themselves, unless they are quoted or don't match anything.
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Orders `frame_2` before `frame_10`.
+fn natural_cmp(a: &Path, b: &Path) -> Ordering {
+    natord::compare(&a.to_string_lossy(), &b.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory for a test, removed first if a previous run left it.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("imspect-paths-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(dir: &Path, names: &[&str]) {
+        for name in names {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+    }
+
+    fn names(dir: &Path, paths: &[PathBuf]) -> Vec<String> {
+        paths
+            .iter()
+            .map(|path| {
+                path.strip_prefix(dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    fn expansion(recursive: bool, extensions: &[&str]) -> Expansion {
+        Expansion {
+            recursive,
+            extensions: extensions.iter().map(|ext| ext.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn natural_order_compares_numbers_by_value() {
+        let mut paths: Vec<PathBuf> = ["img10.png", "img2.png", "img1.png", "img2b.png"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        paths.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            paths,
+            ["img1.png", "img2.png", "img2b.png", "img10.png"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn patterns_are_recognized_by_wildcards() {
+        assert!(is_pattern(Path::new("frames/*.png")));
+        assert!(is_pattern(Path::new("frame_?.png")));
+        assert!(is_pattern(Path::new("frame_[0-9].png")));
+        assert!(!is_pattern(Path::new("frames/frame_1.png")));
+    }
+
+    #[test]
+    fn accepts_image_extensions_in_any_case() {
+        let default = expansion(false, &[]);
+        assert!(default.accepts(Path::new("a.PNG")));
+        assert!(default.accepts(Path::new("a.npz")));
+        assert!(!default.accepts(Path::new("a.txt")));
+        assert!(!default.accepts(Path::new("README")));
+        let chosen = expansion(false, &["npy"]);
+        assert!(chosen.accepts(Path::new("a.NPY")));
+        assert!(!chosen.accepts(Path::new("a.png")));
+    }
+
+    #[test]
+    fn expands_directories_in_natural_order() {
+        let dir = test_dir("dir");
+        touch(
+            &dir,
+            &[
+                "img10.png",
+                "img2.png",
+                "img1.npz",
+                "notes.txt",
+                "sub/img3.png",
+            ],
+        );
+        let (paths, errors) = expansion(false, &[]).expand(vec![dir.clone()]);
+        assert!(errors.is_empty());
+        assert_eq!(names(&dir, &paths), ["img1.npz", "img2.png", "img10.png"]);
+
+        let (paths, _) = expansion(true, &["png"]).expand(vec![dir.clone()]);
+        assert_eq!(
+            names(&dir, &paths),
+            ["img2.png", "img10.png", "sub/img3.png"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expands_patterns_and_keeps_files() {
+        let dir = test_dir("pattern");
+        touch(&dir, &["a2.png", "a10.png", "b1.png", "c.txt"]);
+        let (paths, errors) = expansion(false, &[]).expand(vec![
+            dir.join("a*.png"),
+            dir.join("c.txt"),
+            dir.join("missing.png"),
+        ]);
+        assert!(errors.is_empty());
+        assert_eq!(
+            names(&dir, &paths),
+            ["a2.png", "a10.png", "c.txt", "missing.png"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_empty_expansions() {
+        let dir = test_dir("empty");
+        touch(&dir, &["c.txt"]);
+        let (paths, errors) = expansion(false, &[]).expand(vec![dir.clone(), dir.join("*.png")]);
+        assert!(paths.is_empty());
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].error.to_string(), "No images found");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
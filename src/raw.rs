@@ -0,0 +1,467 @@
+//! Headerless pixel dumps, described by a spec after the path, e.g. `frame.raw:640x480:u16le`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use kornia::image::ImageSize;
+
+use crate::imspect_app::image_kind::{ChannelsKind, DType, ImageKind};
+
+/// Sample types a raw buffer can hold.
+pub const RAW_DTYPES: &[DType] = &[
+    DType::U8,
+    DType::I8,
+    DType::U16,
+    DType::I16,
+    DType::U32,
+    DType::I32,
+    DType::I64,
+    DType::F32,
+    DType::F64,
+];
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+/// Layout of the pixels in a raw buffer:
+/// `WIDTHxHEIGHT[xCHANNELS]:DTYPE[le|be][:stride=BYTES][:offset=BYTES]`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RawSpec {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub dtype: DType,
+    pub endian: Endian,
+    /// Bytes from the start of a row to the start of the next, rows are packed if `None`.
+    pub stride: Option<usize>,
+    /// Bytes skipped at the start of the file.
+    pub offset: usize,
+}
+
+impl Default for RawSpec {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 480,
+            channels: 1,
+            dtype: DType::U8,
+            endian: Endian::Little,
+            stride: None,
+            offset: 0,
+        }
+    }
+}
+
+/// Bytes of a single sample, `None` for types raw buffers can't hold.
+pub fn sample_size(dtype: DType) -> Option<usize> {
+    match dtype {
+        DType::U8 | DType::I8 => Some(1),
+        DType::U16 | DType::I16 => Some(2),
+        DType::U32 | DType::I32 | DType::F32 => Some(4),
+        DType::I64 | DType::F64 => Some(8),
+        DType::Bool => None,
+    }
+}
+
+/// Short name used in specs, e.g. `u16` or `f32`.
+fn dtype_name(dtype: DType) -> &'static str {
+    match dtype {
+        DType::Bool => "bool",
+        DType::U8 => "u8",
+        DType::I8 => "i8",
+        DType::U16 => "u16",
+        DType::I16 => "i16",
+        DType::U32 => "u32",
+        DType::I32 => "i32",
+        DType::I64 => "i64",
+        DType::F32 => "f32",
+        DType::F64 => "f64",
+    }
+}
+
+fn parse_dtype(s: &str) -> Option<(DType, Endian)> {
+    let (name, endian) = if let Some(name) = s.strip_suffix("le") {
+        (name, Endian::Little)
+    } else if let Some(name) = s.strip_suffix("be") {
+        (name, Endian::Big)
+    } else {
+        (s, Endian::Little)
+    };
+    let dtype = RAW_DTYPES
+        .iter()
+        .find(|dtype| dtype_name(**dtype) == name)?;
+    Some((*dtype, endian))
+}
+
+/// `WIDTHxHEIGHT` or `WIDTHxHEIGHTxCHANNELS`.
+fn parse_shape(s: &str) -> Option<(usize, usize, usize)> {
+    let dims: Vec<usize> = s
+        .split('x')
+        .map(|dim| dim.parse().ok())
+        .collect::<Option<_>>()?;
+    match dims[..] {
+        [width, height] => Some((width, height, 1)),
+        [width, height, channels] => Some((width, height, channels)),
+        _ => None,
+    }
+}
+
+impl FromStr for RawSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let shape = parts.next().unwrap_or_default();
+        let (width, height, channels) = parse_shape(shape).ok_or_else(|| {
+            anyhow!(
+                "Expected WIDTHxHEIGHT or WIDTHxHEIGHTxCHANNELS, got '{}'",
+                shape
+            )
+        })?;
+        let dtype = parts
+            .next()
+            .ok_or_else(|| anyhow!("The dtype is missing, e.g. {}:u16le", shape))?;
+        let (dtype, endian) = parse_dtype(dtype).ok_or_else(|| {
+            anyhow!(
+                "Unknown dtype '{}', expected u8, i8, u16, i16, u32, i32, i64, f32 or f64 \
+                 with an optional le/be suffix",
+                dtype
+            )
+        })?;
+        let mut spec = RawSpec {
+            width,
+            height,
+            channels,
+            dtype,
+            endian,
+            ..Default::default()
+        };
+        for option in parts {
+            let (key, value) = option.split_once('=').ok_or_else(|| {
+                anyhow!("Expected stride=BYTES or offset=BYTES, got '{}'", option)
+            })?;
+            let value: usize = value
+                .parse()
+                .with_context(|| format!("Invalid {} '{}'", key, value))?;
+            match key {
+                "stride" => spec.stride = Some(value),
+                "offset" => spec.offset = value,
+                _ => {
+                    return Err(anyhow!(
+                        "Unknown option '{}', expected stride or offset",
+                        key
+                    ))
+                }
+            }
+        }
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for RawSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if self.channels != 1 {
+            write!(f, "x{}", self.channels)?;
+        };
+        write!(f, ":{}", dtype_name(self.dtype))?;
+        if sample_size(self.dtype).unwrap_or(1) > 1 {
+            let endian = match self.endian {
+                Endian::Little => "le",
+                Endian::Big => "be",
+            };
+            write!(f, "{}", endian)?;
+        };
+        if let Some(stride) = self.stride {
+            write!(f, ":stride={}", stride)?;
+        };
+        if self.offset != 0 {
+            write!(f, ":offset={}", self.offset)?;
+        };
+        Ok(())
+    }
+}
+
+/// Splits `frame.raw:640x480:u16le` into the file and the spec,
+/// `None` for paths that exist or don't end with a spec.
+pub fn split_arg(arg: &Path) -> Option<(PathBuf, &str)> {
+    if arg.exists() {
+        return None;
+    };
+    let arg = arg.to_str()?;
+    // the path may contain colons itself, the spec starts at the first shape after one
+    arg.match_indices(':').find_map(|(i, _)| {
+        let spec = &arg[i + 1..];
+        let shape = spec.split(':').next()?;
+        parse_shape(shape)?;
+        Some((PathBuf::from(&arg[..i]), spec))
+    })
+}
+
+/// The argument that opens `file` with `spec`, the inverse of [`split_arg`].
+pub fn join_arg(file: &Path, spec: &impl fmt::Display) -> PathBuf {
+    let mut arg = file.as_os_str().to_owned();
+    arg.push(format!(":{}", spec));
+    PathBuf::from(arg)
+}
+
+/// Converts every `N` bytes to a sample.
+fn samples<T, const N: usize>(
+    bytes: &[u8],
+    endian: Endian,
+    from_le: fn([u8; N]) -> T,
+    from_be: fn([u8; N]) -> T,
+) -> Vec<T> {
+    let from_bytes = match endian {
+        Endian::Little => from_le,
+        Endian::Big => from_be,
+    };
+    bytes
+        .chunks_exact(N)
+        .map(|chunk| from_bytes(chunk.try_into().expect("chunks have N bytes")))
+        .collect()
+}
+
+impl RawSpec {
+    /// Bytes of a packed row, `None` if the count overflows.
+    pub fn row_len(&self) -> Option<usize> {
+        self.width
+            .checked_mul(self.channels)?
+            .checked_mul(sample_size(self.dtype)?)
+    }
+
+    /// Bytes from the start of the file to the end of the last row, `None` if the count overflows.
+    fn needed_len(&self) -> Option<usize> {
+        let row_len = self.row_len()?;
+        self.stride
+            .unwrap_or(row_len)
+            .checked_mul(self.height.checked_sub(1)?)?
+            .checked_add(row_len)?
+            .checked_add(self.offset)
+    }
+
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.width == 0 || self.height == 0 {
+            return Err(anyhow!("The width and height must be positive"));
+        };
+        if !(1..=4).contains(&self.channels) {
+            return Err(anyhow!(
+                "Images have 1 to 4 channels, got {}",
+                self.channels
+            ));
+        };
+        if sample_size(self.dtype).is_none() {
+            return Err(anyhow!("Raw buffers can't hold {} samples", self.dtype));
+        };
+        let Some(row_len) = self.row_len() else {
+            return Err(anyhow!("A row of {} is too long", self));
+        };
+        if let Some(stride) = self.stride {
+            if stride < row_len {
+                return Err(anyhow!(
+                    "A stride of {} bytes is shorter than a row of {} bytes",
+                    stride,
+                    row_len
+                ));
+            };
+        };
+        if self.needed_len().is_none() {
+            return Err(anyhow!(
+                "{} describes more bytes than a file can hold",
+                self
+            ));
+        };
+        Ok(())
+    }
+
+    /// Copies the rows out of the buffer and converts them to the image type.
+    pub fn decode(&self, bytes: &[u8]) -> Result<ImageKind, anyhow::Error> {
+        self.validate()?;
+        let (Some(row_len), Some(needed)) = (self.row_len(), self.needed_len()) else {
+            return Err(anyhow!(
+                "{} describes more bytes than a file can hold",
+                self
+            ));
+        };
+        let stride = self.stride.unwrap_or(row_len);
+        if bytes.len() < needed {
+            return Err(anyhow!(
+                "The file has {} bytes, {} needs {}",
+                bytes.len(),
+                self,
+                needed
+            ));
+        };
+        let mut data = Vec::with_capacity(row_len * self.height);
+        for row in 0..self.height {
+            let start = self.offset + row * stride;
+            data.extend_from_slice(&bytes[start..start + row_len]);
+        }
+
+        let size = ImageSize {
+            width: self.width,
+            height: self.height,
+        };
+        let channels = self.channels;
+        let widened = |values: Vec<f32>| -> Result<ImageKind, anyhow::Error> {
+            Ok(ImageKind::F32(
+                ChannelsKind::new(size, channels, values)?,
+                self.dtype,
+            ))
+        };
+        let e = self.endian;
+        match self.dtype {
+            DType::U8 => Ok(ImageKind::U8(ChannelsKind::new(size, channels, data)?)),
+            DType::U16 => Ok(ImageKind::U16(ChannelsKind::new(
+                size,
+                channels,
+                samples(&data, e, u16::from_le_bytes, u16::from_be_bytes),
+            )?)),
+            DType::F32 => widened(samples(&data, e, f32::from_le_bytes, f32::from_be_bytes)),
+            DType::I8 => widened(data.iter().map(|&v| v as i8 as f32).collect()),
+            DType::I16 => widened(
+                samples(&data, e, i16::from_le_bytes, i16::from_be_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            ),
+            DType::U32 => widened(
+                samples(&data, e, u32::from_le_bytes, u32::from_be_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            ),
+            DType::I32 => widened(
+                samples(&data, e, i32::from_le_bytes, i32::from_be_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            ),
+            DType::I64 => widened(
+                samples(&data, e, i64::from_le_bytes, i64::from_be_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            ),
+            DType::F64 => widened(
+                samples(&data, e, f64::from_le_bytes, f64::from_be_bytes)
+                    .into_iter()
+                    .map(|v| v as f32)
+                    .collect(),
+            ),
+            DType::Bool => Err(anyhow!("Raw buffers can't hold bool samples")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_round_trips() {
+        for text in [
+            "640x480:u8",
+            "640x480x3:u16be",
+            "8x2x4:f32le:stride=160:offset=16",
+            "1x1:i64le:offset=3",
+        ] {
+            let spec: RawSpec = text.parse().unwrap();
+            assert_eq!(spec.to_string(), text);
+            assert_eq!(spec.to_string().parse::<RawSpec>().unwrap(), spec);
+        }
+    }
+
+    #[test]
+    fn spec_defaults_to_little_endian() {
+        let spec: RawSpec = "4x2x2:u16".parse().unwrap();
+        assert_eq!(spec.endian, Endian::Little);
+        assert_eq!((spec.width, spec.height, spec.channels), (4, 2, 2));
+        assert_eq!(spec.to_string(), "4x2x2:u16le");
+    }
+
+    #[test]
+    fn spec_rejects() {
+        for text in [
+            "",
+            "640",
+            "640x480",
+            "640x480x3x1:u8",
+            "0x480:u8",
+            "640x0:u8",
+            "640x480x5:u8",
+            "640x480:u12",
+            "640x480:bool",
+            "640x480:u16:stride=1279",
+            "640x480:u8:stride",
+            "640x480:u8:stride=-1",
+            "640x480:u8:gap=4",
+            "18446744073709551615x2x4:f64",
+            "2x18446744073709551615:u8:stride=2",
+            "2x2:u8:offset=18446744073709551615",
+        ] {
+            assert!(text.parse::<RawSpec>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn split_arg_finds_the_spec() {
+        let (file, spec) = split_arg(Path::new("missing/frame.raw:640x480:u16le")).unwrap();
+        assert_eq!(file, Path::new("missing/frame.raw"));
+        assert_eq!(spec, "640x480:u16le");
+
+        let (file, spec) = split_arg(Path::new("C:/missing/frame.raw:4x4x3:u8")).unwrap();
+        assert_eq!(file, Path::new("C:/missing/frame.raw"));
+        assert_eq!(spec, "4x4x3:u8");
+
+        assert!(split_arg(Path::new("missing/notes:todo.txt")).is_none());
+        assert!(split_arg(Path::new("missing/frame.raw")).is_none());
+    }
+
+    #[test]
+    fn join_arg_inverts_split_arg() {
+        let spec: RawSpec = "4x2:u16be:offset=8".parse().unwrap();
+        let arg = join_arg(Path::new("missing/frame.raw"), &spec);
+        let (file, text) = split_arg(&arg).unwrap();
+        assert_eq!(file, Path::new("missing/frame.raw"));
+        assert_eq!(text.parse::<RawSpec>().unwrap(), spec);
+    }
+
+    #[test]
+    fn decode_skips_offset_and_stride_padding() {
+        let spec: RawSpec = "2x2:u16be:stride=6:offset=1".parse().unwrap();
+        let bytes = [0xff, 0, 1, 0, 2, 0xff, 0xff, 0, 3, 0, 4];
+        let ImageKind::U16(img) = spec.decode(&bytes).unwrap() else {
+            panic!("u16 samples decode to a u16 image");
+        };
+        assert_eq!(img.as_slice(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn decode_rejects_short_buffers() {
+        let spec: RawSpec = "2x2:u16le".parse().unwrap();
+        assert!(spec.decode(&[0; 7]).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_overflowing_specs() {
+        let spec = RawSpec {
+            width: usize::MAX / 2,
+            channels: 4,
+            ..Default::default()
+        };
+        assert!(spec.decode(&[0; 16]).is_err());
+        let spec = RawSpec {
+            offset: usize::MAX,
+            ..Default::default()
+        };
+        assert!(spec.decode(&[0; 16]).is_err());
+    }
+}
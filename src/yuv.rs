@@ -0,0 +1,436 @@
+//! 8-bit YUV frames from video pipelines, e.g. `frame.yuv:1920x1080:nv12`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use kornia::image::{ImageError, ImageSize};
+
+use crate::imspect_app::image_kind::{ChannelsKind, ImageKind};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum YuvFormat {
+    /// Y plane, then interleaved U/V at half the width and height.
+    Nv12,
+    /// Like NV12 with V before U.
+    Nv21,
+    /// Y, U and V planes, the chroma ones at half the width and height.
+    I420,
+    /// Packed `Y0 U Y1 V`, chroma at half the width.
+    Yuyv,
+}
+
+pub const YUV_FORMATS: &[YuvFormat] = &[
+    YuvFormat::Nv12,
+    YuvFormat::Nv21,
+    YuvFormat::I420,
+    YuvFormat::Yuyv,
+];
+
+impl YuvFormat {
+    /// Subsampling of the chroma planes along x and y.
+    fn chroma_step(&self) -> (usize, usize) {
+        match self {
+            YuvFormat::Nv12 | YuvFormat::Nv21 | YuvFormat::I420 => (2, 2),
+            YuvFormat::Yuyv => (2, 1),
+        }
+    }
+}
+
+impl FromStr for YuvFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nv12" => Ok(YuvFormat::Nv12),
+            "nv21" => Ok(YuvFormat::Nv21),
+            "i420" | "yuv420p" => Ok(YuvFormat::I420),
+            "yuyv" | "yuy2" => Ok(YuvFormat::Yuyv),
+            _ => Err(anyhow!(
+                "Unknown YUV format '{}', expected nv12, nv21, i420 or yuyv",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for YuvFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            YuvFormat::Nv12 => "nv12",
+            YuvFormat::Nv21 => "nv21",
+            YuvFormat::I420 => "i420",
+            YuvFormat::Yuyv => "yuyv",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How Y, U and V values map to RGB.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum YuvRange {
+    /// Video levels, Y in `16..=235` and chroma in `16..=240`.
+    #[default]
+    Limited,
+    /// Every value in `0..=255` is used, as in JPEG.
+    Full,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum YuvPlane {
+    Y,
+    U,
+    V,
+}
+
+impl fmt::Display for YuvPlane {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Layout of YUV frames: `WIDTHxHEIGHT:FORMAT[:full]`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct YuvSpec {
+    pub width: usize,
+    pub height: usize,
+    pub format: YuvFormat,
+    pub range: YuvRange,
+}
+
+impl YuvSpec {
+    /// Whether `spec` names a YUV format rather than a sample type.
+    pub fn matches(spec: &str) -> bool {
+        spec.split(':')
+            .nth(1)
+            .is_some_and(|format| format.parse::<YuvFormat>().is_ok())
+    }
+
+    fn chroma_size(&self) -> (usize, usize) {
+        let (step_x, step_y) = self.format.chroma_step();
+        (self.width.div_ceil(step_x), self.height.div_ceil(step_y))
+    }
+
+    /// Bytes of a single frame, `None` if the count overflows.
+    pub fn frame_len(&self) -> Option<usize> {
+        let (chroma_w, chroma_h) = self.chroma_size();
+        self.width
+            .checked_mul(self.height)?
+            .checked_add(chroma_w.checked_mul(chroma_h)?.checked_mul(2)?)
+    }
+
+    /// Splits the buffer into frames, a trailing partial frame is an error.
+    pub fn frames(&self, bytes: &[u8]) -> Result<Vec<YuvFrame>, anyhow::Error> {
+        let frame_len = self
+            .frame_len()
+            .ok_or_else(|| anyhow!("{} frames are too large", self))?;
+        if bytes.len() < frame_len || bytes.len() % frame_len != 0 {
+            return Err(anyhow!(
+                "The file has {} bytes, not a whole number of {} bytes {}x{} {} frames",
+                bytes.len(),
+                frame_len,
+                self.width,
+                self.height,
+                self.format
+            ));
+        };
+        Ok(bytes
+            .chunks_exact(frame_len)
+            .map(|data| YuvFrame {
+                spec: self.clone(),
+                data: data.to_vec(),
+            })
+            .collect())
+    }
+}
+
+impl FromStr for YuvSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let shape = parts.next().unwrap_or_default();
+        let (width, height) = shape
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)))
+            .filter(|&(w, h)| w > 0 && h > 0)
+            .ok_or_else(|| anyhow!("Expected WIDTHxHEIGHT, got '{}'", shape))?;
+        let format = parts.next().unwrap_or_default().parse()?;
+        let range = match parts.next() {
+            None => YuvRange::Limited,
+            Some("full") => YuvRange::Full,
+            Some("limited") => YuvRange::Limited,
+            Some(other) => {
+                return Err(anyhow!(
+                    "Unknown range '{}', expected full or limited",
+                    other
+                ))
+            }
+        };
+        if let Some(extra) = parts.next() {
+            return Err(anyhow!("Unexpected '{}' after the YUV range", extra));
+        };
+        if format == YuvFormat::Yuyv && width % 2 != 0 {
+            return Err(anyhow!("YUYV frames have an even width, got {}", width));
+        };
+        let spec = YuvSpec {
+            width,
+            height,
+            format,
+            range,
+        };
+        if spec.frame_len().is_none() {
+            return Err(anyhow!("{} frames are too large", spec));
+        };
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for YuvSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}:{}", self.width, self.height, self.format)?;
+        if self.range == YuvRange::Full {
+            write!(f, ":full")?;
+        };
+        Ok(())
+    }
+}
+
+/// The bytes of one frame, kept so the planes can be shown at their own resolution.
+#[derive(Clone)]
+pub struct YuvFrame {
+    spec: YuvSpec,
+    data: Vec<u8>,
+}
+
+impl YuvFrame {
+    /// Values of a plane, row by row, with its width and height.
+    fn plane_data(&self, plane: YuvPlane) -> (Vec<u8>, usize, usize) {
+        let YuvSpec { width, height, .. } = self.spec;
+        let (chroma_w, chroma_h) = self.spec.chroma_size();
+        let luma_len = width * height;
+        let chroma_len = chroma_w * chroma_h;
+        let data = &self.data;
+        let values = match (self.spec.format, plane) {
+            (YuvFormat::Yuyv, YuvPlane::Y) => {
+                let row_len = 4 * chroma_w;
+                (0..height)
+                    .flat_map(|y| (0..width).map(move |x| data[y * row_len + 2 * x]))
+                    .collect()
+            }
+            (YuvFormat::Yuyv, _) => {
+                let row_len = 4 * chroma_w;
+                let at = if plane == YuvPlane::U { 1 } else { 3 };
+                (0..chroma_h)
+                    .flat_map(|y| (0..chroma_w).map(move |x| data[y * row_len + 4 * x + at]))
+                    .collect()
+            }
+            (_, YuvPlane::Y) => data[..luma_len].to_vec(),
+            (YuvFormat::I420, YuvPlane::U) => data[luma_len..luma_len + chroma_len].to_vec(),
+            (YuvFormat::I420, YuvPlane::V) => data[luma_len + chroma_len..].to_vec(),
+            (YuvFormat::Nv12 | YuvFormat::Nv21, _) => {
+                let first = (plane == YuvPlane::U) == (self.spec.format == YuvFormat::Nv12);
+                let at = if first { 0 } else { 1 };
+                data[luma_len..]
+                    .chunks_exact(2)
+                    .map(|pair| pair[at])
+                    .collect()
+            }
+        };
+        match plane {
+            YuvPlane::Y => (values, width, height),
+            YuvPlane::U | YuvPlane::V => (values, chroma_w, chroma_h),
+        }
+    }
+
+    /// A plane as a one-channel image at its native resolution.
+    pub fn plane(&self, plane: YuvPlane) -> Result<ImageKind, ImageError> {
+        let (values, width, height) = self.plane_data(plane);
+        let size = ImageSize { width, height };
+        Ok(ImageKind::U8(ChannelsKind::new(size, 1, values)?))
+    }
+
+    /// BT.601 conversion, chroma is repeated over the pixels that share it.
+    pub fn to_rgb(&self) -> Result<ImageKind, ImageError> {
+        let YuvSpec { width, height, .. } = self.spec;
+        let (step_x, step_y) = self.spec.format.chroma_step();
+        let (chroma_w, _) = self.spec.chroma_size();
+        let (y_plane, _, _) = self.plane_data(YuvPlane::Y);
+        let (u_plane, _, _) = self.plane_data(YuvPlane::U);
+        let (v_plane, _, _) = self.plane_data(YuvPlane::V);
+        let (luma_scale, chroma_scale, luma_offset) = match self.spec.range {
+            YuvRange::Limited => (255. / 219., 255. / 224., 16.),
+            YuvRange::Full => (1., 1., 0.),
+        };
+
+        let mut rgb = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                let chroma_i = (y / step_y) * chroma_w + x / step_x;
+                let luma = (y_plane[y * width + x] as f32 - luma_offset) * luma_scale;
+                let u = (u_plane[chroma_i] as f32 - 128.) * chroma_scale;
+                let v = (v_plane[chroma_i] as f32 - 128.) * chroma_scale;
+                rgb.extend(
+                    [
+                        luma + 1.402 * v,
+                        luma - 0.344136 * u - 0.714136 * v,
+                        luma + 1.772 * u,
+                    ]
+                    .map(|value| value.round().clamp(0., 255.) as u8),
+                );
+            }
+        }
+        let size = ImageSize { width, height };
+        Ok(ImageKind::U8(ChannelsKind::new(size, 3, rgb)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_round_trips() {
+        for text in ["1920x1080:nv12", "4x2:nv21:full", "3x3:i420", "4x2:yuyv"] {
+            let spec: YuvSpec = text.parse().unwrap();
+            assert_eq!(spec.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn spec_accepts_aliases_and_ranges() {
+        let spec: YuvSpec = "4x2:YUY2:limited".parse().unwrap();
+        assert_eq!(spec.format, YuvFormat::Yuyv);
+        assert_eq!(spec.range, YuvRange::Limited);
+        assert_eq!(spec.to_string(), "4x2:yuyv");
+        let spec: YuvSpec = "4x2:yuv420p".parse().unwrap();
+        assert_eq!(spec.format, YuvFormat::I420);
+    }
+
+    #[test]
+    fn spec_rejects() {
+        for text in [
+            "",
+            "4x2",
+            "4:nv12",
+            "0x2:nv12",
+            "4x0:nv12",
+            "4x2:rgb",
+            "3x2:yuyv",
+            "4x2:nv12:wide",
+            "4x2:nv12:full:more",
+            "18446744073709551615x2:nv12",
+        ] {
+            assert!(text.parse::<YuvSpec>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn matches_only_yuv_formats() {
+        assert!(YuvSpec::matches("4x2:nv12"));
+        assert!(YuvSpec::matches("4x2:I420:full"));
+        assert!(!YuvSpec::matches("4x2:u8"));
+        assert!(!YuvSpec::matches("4x2"));
+    }
+
+    #[test]
+    fn frame_len_rounds_chroma_up() {
+        let spec: YuvSpec = "3x3:i420".parse().unwrap();
+        assert_eq!(spec.frame_len(), Some(9 + 2 * 4));
+        let spec: YuvSpec = "4x2:yuyv".parse().unwrap();
+        assert_eq!(spec.frame_len(), Some(16));
+    }
+
+    #[test]
+    fn frames_split_whole_frames_only() {
+        let spec: YuvSpec = "2x2:nv12".parse().unwrap();
+        assert_eq!(spec.frames(&[0; 12]).unwrap().len(), 2);
+        assert!(spec.frames(&[0; 9]).is_err());
+        assert!(spec.frames(&[0; 5]).is_err());
+    }
+
+    #[test]
+    fn planes_of_nv21_swap_chroma() {
+        let spec: YuvSpec = "2x2:nv21:full".parse().unwrap();
+        let frame = spec.frames(&[1, 2, 3, 4, 20, 10]).unwrap().remove(0);
+        let (u, w, h) = frame.plane_data(YuvPlane::U);
+        assert_eq!((u, w, h), (vec![10], 1, 1));
+        let (v, _, _) = frame.plane_data(YuvPlane::V);
+        assert_eq!(v, vec![20]);
+        let (y, _, _) = frame.plane_data(YuvPlane::Y);
+        assert_eq!(y, vec![1, 2, 3, 4]);
+    }
+
+    /// RGB pixels of the first frame of `data`.
+    fn rgb(spec: &str, data: &[u8]) -> Vec<u8> {
+        let spec: YuvSpec = spec.parse().unwrap();
+        match spec.frames(data).unwrap()[0].to_rgb().unwrap() {
+            ImageKind::U8(img) => img.as_slice().to_vec(),
+            _ => panic!("expected an 8-bit image"),
+        }
+    }
+
+    const RED: [u8; 3] = [254, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 254];
+
+    /// Red left half and blue right half of a full range 4x2 frame.
+    fn red_and_blue() -> Vec<u8> {
+        [RED, RED, BLUE, BLUE, RED, RED, BLUE, BLUE].concat()
+    }
+
+    #[test]
+    fn limited_range_maps_video_levels_to_black_and_white() {
+        assert_eq!(
+            rgb("2x2:nv12", &[16, 235, 235, 16, 128, 128]),
+            [[0; 3], [255; 3], [255; 3], [0; 3]].concat()
+        );
+        assert_eq!(
+            rgb("2x1:yuyv", &[16, 128, 235, 128]),
+            [[0; 3], [255; 3]].concat()
+        );
+    }
+
+    #[test]
+    fn full_range_keeps_every_level() {
+        assert_eq!(
+            rgb("2x2:i420:full", &[0, 255, 255, 0, 128, 128]),
+            [[0; 3], [255; 3], [255; 3], [0; 3]].concat()
+        );
+    }
+
+    #[test]
+    fn nv12_reads_interleaved_chroma() {
+        // red is Y 76, U 85, V 255 and blue Y 29, U 255, V 107
+        let luma = [76, 76, 29, 29, 76, 76, 29, 29];
+        let frame = [&luma[..], &[85, 255, 255, 107]].concat();
+        assert_eq!(rgb("4x2:nv12:full", &frame), red_and_blue());
+        let frame = [&luma[..], &[255, 85, 107, 255]].concat();
+        assert_eq!(rgb("4x2:nv21:full", &frame), red_and_blue());
+    }
+
+    #[test]
+    fn i420_reads_chroma_planes() {
+        let luma = [76, 76, 29, 29, 76, 76, 29, 29];
+        let frame = [&luma[..], &[85, 255], &[255, 107]].concat();
+        assert_eq!(rgb("4x2:i420:full", &frame), red_and_blue());
+    }
+
+    #[test]
+    fn yuyv_reads_packed_pairs() {
+        let row = [76, 85, 76, 255, 29, 255, 29, 107];
+        let frame = [row, row].concat();
+        assert_eq!(rgb("4x2:yuyv:full", &frame), red_and_blue());
+    }
+
+    #[test]
+    fn later_frames_are_converted_alone() {
+        let spec: YuvSpec = "2x2:nv12:full".parse().unwrap();
+        let frames = spec
+            .frames(&[0, 0, 0, 0, 128, 128, 255, 255, 255, 255, 128, 128])
+            .unwrap();
+        let ImageKind::U8(white) = frames[1].to_rgb().unwrap() else {
+            panic!("expected an 8-bit image");
+        };
+        assert_eq!(white.as_slice(), &[255; 12]);
+    }
+}